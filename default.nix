@@ -12,7 +12,7 @@ pkgs.rustPlatform.buildRustPackage rec {
   ];
 
   buildInputs = [
-    pkgs.dbus pkgs.udev pkgs.libinput
+    pkgs.dbus pkgs.udev pkgs.libinput pkgs.libxkbcommon
   ];
 
   meta = {
@@ -1,7 +1,16 @@
 mod command_action;
+mod composite;
 pub mod consumer;
+mod dbus_action;
+mod key_macro;
+mod layer_action;
+mod media_action;
+mod mouse_action;
+mod pipe_action;
+mod sway_action;
 mod uinput_action;
 
+use crate::gesture_event::Context;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -9,8 +18,47 @@ use thiserror::Error;
 pub struct ActionError(pub String);
 
 pub trait Action {
-    fn execute(&mut self) -> Result<(), ActionError>;
+    /// The context tells what fired the trigger
+    fn execute(&mut self, context: &Context) -> Result<(), ActionError>;
+
+    /// Action only changes the state of wzmach itself, so it's safe to execute
+    /// while debugging gestures
+    fn internal(&self) -> bool {
+        false
+    }
 }
 
-pub use command_action::{CommandAction, ShellCommandAction};
-pub use uinput_action::UinputAction;
+pub use command_action::{CommandAction, Lifecycle, ShellCommandAction};
+pub use composite::{IfAction, ParallelAction, SequenceAction};
+pub use dbus_action::{method_call, Buses, DBusAction};
+pub use key_macro::{KeyMacroAction, KeyStep};
+pub use layer_action::SwitchLayerAction;
+pub use media_action::{LastPlayer, MediaAction};
+pub use mouse_action::{
+    create_mouse, pointer_device, send_scroll, MouseClickAction, PointerMotionAction, ScrollAction,
+    VIRTUAL_POINTER,
+};
+pub use pipe_action::{PipeAction, Pipes};
+pub use sway_action::SwayAction;
+pub use uinput_action::{is_key_code, KeyChord, UinputAction, VIRTUAL_KEYBOARD};
+
+/// Helpers for tests that run commands and wait for what they do
+#[cfg(test)]
+pub(crate) mod test {
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    /// Path in the temporary directory that's unique to this test run
+    pub fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("wzmach-{}-{}", name, std::process::id()))
+    }
+
+    /// Poll until the condition holds, failing after a few seconds
+    pub fn wait_for(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out waiting");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -1,71 +1,202 @@
-use std::os::unix::prelude::CommandExt;
+use std::io::BufRead;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use super::{Action, ActionError};
+use crate::common::CommandMode;
+use crate::gesture_event::{Context, GestureCount};
+
+/// How often to check on commands that have a time limit
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long commands get to exit after being asked to, before they are killed
+const KILL_GRACE: Duration = Duration::from_secs(1);
 
 pub struct CommandAction {
     pub path: String,
     pub args: Vec<String>,
+    pub lifecycle: Lifecycle,
 }
 
 pub struct ShellCommandAction {
     pub command: String,
+    pub lifecycle: Lifecycle,
 }
 
-impl Action for CommandAction {
-    fn execute(&mut self) -> Result<(), ActionError> {
-        log::debug!("Execute command {} {:?}", self.path, self.args);
+/// What happens to the commands an action starts. Unless they are waited for,
+/// commands are watched from their own threads, which log how they exit and
+/// what they write to stderr
+pub struct Lifecycle {
+    pub mode: CommandMode,
+    pub timeout: Option<Duration>,
+    /// Stop the command when the gesture that started it ends. Triggers like
+    /// Tap fire once the gesture has ended, so their commands run until the
+    /// next gesture ends
+    pub kill_on_gesture_end: bool,
+    pub gestures: GestureCount,
+    /// A command of this action is still running
+    running: Arc<AtomicBool>,
+}
 
-        std::process::Command::new(&self.path)
-            .args(self.args.iter())
-            .stdin(std::process::Stdio::null())
-            .stdout(std::process::Stdio::inherit())
-            .stderr(std::process::Stdio::inherit())
-            .env_remove("DBUS_SYSTEM_BUS_ADDRESS")
-            .detach()?;
-        log::trace!("Spawned the command");
+impl Action for CommandAction {
+    fn execute(&mut self, context: &Context) -> Result<(), ActionError> {
+        let args = self
+            .args
+            .iter()
+            .map(|a| context.substitute(a))
+            .collect::<Vec<_>>();
+        log::debug!("Execute command {} {:?}", self.path, args);
 
-        Ok(())
+        let mut command = std::process::Command::new(&self.path);
+        command
+            .args(args)
+            .envs(context.env())
+            .stdin(std::process::Stdio::null());
+        self.lifecycle.run(command, &self.path)
     }
 }
 
 impl Action for ShellCommandAction {
-    fn execute(&mut self) -> Result<(), ActionError> {
+    fn execute(&mut self, context: &Context) -> Result<(), ActionError> {
         log::debug!("Execute command {:?}", self.command);
 
-        std::process::Command::new("/bin/sh")
-            .arg("-c")
-            .arg(&self.command)
+        let mut command = std::process::Command::new("/bin/sh");
+        command.arg("-c").arg(&self.command).envs(context.env());
+        let name = self.command.trim().lines().next().unwrap_or_default();
+        self.lifecycle.run(command, name)
+    }
+}
+
+impl Lifecycle {
+    pub fn new(
+        mode: CommandMode,
+        timeout: Option<Duration>,
+        kill_on_gesture_end: bool,
+        gestures: GestureCount,
+    ) -> Self {
+        Lifecycle {
+            mode,
+            timeout,
+            kill_on_gesture_end,
+            gestures,
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn run(&self, mut command: std::process::Command, name: &str) -> Result<(), ActionError> {
+        if self.mode == CommandMode::Singleton && self.running.load(Ordering::SeqCst) {
+            log::info!("Not starting {}, it's still running", name);
+            return Ok(());
+        }
+        // Own process group, so that the whole group can be stopped, with
+        // whatever the shell started
+        let mut child = command
             .stdout(std::process::Stdio::inherit())
-            .stderr(std::process::Stdio::inherit())
+            .stderr(std::process::Stdio::piped())
             .env_remove("DBUS_SYSTEM_BUS_ADDRESS")
-            .detach()?;
+            .process_group(0)
+            .spawn()?;
         log::trace!("Spawned the command");
+        self.running.store(true, Ordering::SeqCst);
+
+        log_stderr(&mut child, name);
+        let name = name.to_owned();
+        let watch = Watch {
+            deadline: self.timeout.map(|t| Instant::now() + t),
+            gesture: self
+                .kill_on_gesture_end
+                .then(|| (self.gestures.clone(), self.gestures.get())),
+        };
+        let running = self.running.clone();
+        let mut finish = move || {
+            let status = watch.supervise(&mut child, &name);
+            running.store(false, Ordering::SeqCst);
+            match status {
+                Ok(status) if status.success() => {
+                    log::debug!("{} exited successfully", name);
+                    Ok(())
+                }
+                Ok(status) => Err(ActionError(format!("{} failed: {}", name, status))),
+                Err(e) => Err(ActionError(format!("waiting for {}: {}", name, e))),
+            }
+        };
+        if self.mode == CommandMode::Wait {
+            finish()
+        } else {
+            std::thread::spawn(move || {
+                if let Err(e) = finish() {
+                    log::error!("{}", e);
+                }
+            });
+            Ok(())
+        }
+    }
+}
 
-        Ok(())
+/// Log what the command writes to its piped stderr, from a thread of its own
+pub(super) fn log_stderr(child: &mut Child, name: &str) {
+    if let Some(stderr) = child.stderr.take() {
+        let name = name.to_owned();
+        std::thread::spawn(move || {
+            for line in std::io::BufReader::new(stderr)
+                .lines()
+                .map_while(Result::ok)
+            {
+                log::warn!("{}: {}", name, line);
+            }
+        });
     }
 }
 
-/// Extension to be able to detach child processes without creating zombies
-trait DetachExt {
-    fn detach(&mut self) -> std::io::Result<()>;
+/// When to stop a running command. It must lead its own process group
+pub(super) struct Watch {
+    pub deadline: Option<Instant>,
+    /// Gesture counter and its value when the command started
+    pub gesture: Option<(GestureCount, u64)>,
 }
-impl DetachExt for std::process::Command {
-    fn detach(&mut self) -> std::io::Result<()> {
-        // Safety: usual daemonization stuff. Parent exits immediately before
-        // exec, child goes on to exec what it wants
-        use nix::unistd::ForkResult;
-        unsafe {
-            self.pre_exec(|| {
-                match nix::unistd::fork().unwrap() {
-                    ForkResult::Parent { child: _ } => std::process::exit(0),
-                    ForkResult::Child => (),
+
+impl Watch {
+    pub fn supervise(&self, child: &mut Child, name: &str) -> std::io::Result<ExitStatus> {
+        if self.deadline.is_none() && self.gesture.is_none() {
+            return child.wait();
+        }
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+            if self.deadline.is_some_and(|d| Instant::now() >= d) {
+                log::error!("{} timed out, stopping it", name);
+                return stop(child);
+            }
+            if let Some((ref gestures, started)) = self.gesture {
+                if gestures.get() != started {
+                    log::debug!("Gesture ended, stopping {}", name);
+                    return stop(child);
                 }
-                Ok(())
-            })
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Ask the command and everything it started to exit, then kill them
+pub(super) fn stop(child: &mut Child) -> std::io::Result<ExitStatus> {
+    use nix::sys::signal::{killpg, Signal};
+    let group = nix::unistd::Pid::from_raw(child.id() as i32);
+    // The group may be gone already, which is fine
+    let _ = killpg(group, Signal::SIGTERM);
+    let deadline = Instant::now() + KILL_GRACE;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
         }
-        .status()
-        .map(|_| ())
+        std::thread::sleep(POLL_INTERVAL);
     }
+    let _ = killpg(group, Signal::SIGKILL);
+    child.wait()
 }
 
 impl From<std::io::Error> for ActionError {
@@ -73,3 +204,67 @@ impl From<std::io::Error> for ActionError {
         ActionError(format!("{}", err))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Lifecycle, ShellCommandAction};
+    use crate::action::test::{temp_path, wait_for};
+    use crate::action::Action;
+    use crate::common::CommandMode;
+    use crate::gesture_event::{Context, GestureCount};
+
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn lifecycle() {
+        let shell = |command: &str, mode, timeout| ShellCommandAction {
+            command: command.to_owned(),
+            lifecycle: Lifecycle::new(mode, timeout, false, GestureCount::default()),
+        };
+        let context = Context::default();
+
+        let mut failing = shell("exit 3", CommandMode::Wait, None);
+        let e = failing.execute(&context).unwrap_err();
+        assert_eq!(e.0, "exit 3 failed: exit status: 3");
+
+        let start = Instant::now();
+        let mut slow = shell(
+            "sleep 10",
+            CommandMode::Wait,
+            Some(Duration::from_millis(100)),
+        );
+        assert!(slow.execute(&context).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // the second one is skipped while the first one waits for release
+        let dir = temp_path("singleton");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (path, release) = (dir.join("written"), dir.join("release"));
+        let command = format!(
+            "echo x >> {}; while [ ! -e {} ]; do sleep 0.01; done",
+            path.display(),
+            release.display()
+        );
+        let written = || std::fs::read_to_string(&path).unwrap_or_default();
+        let mut single = shell(&command, CommandMode::Singleton, None);
+        single.execute(&context).unwrap();
+        single.execute(&context).unwrap();
+        std::fs::write(&release, "").unwrap();
+        wait_for(|| !single.lifecycle.running.load(Ordering::SeqCst));
+        assert_eq!(written(), "x\n");
+        single.execute(&context).unwrap();
+        wait_for(|| written() == "x\nx\n");
+        wait_for(|| !single.lifecycle.running.load(Ordering::SeqCst));
+
+        // detached commands without options are watched too, without waiting
+        std::fs::remove_file(&release).unwrap();
+        let mut detach = shell(&command, CommandMode::Detach, None);
+        detach.execute(&context).unwrap();
+        assert!(detach.lifecycle.running.load(Ordering::SeqCst));
+        wait_for(|| written() == "x\nx\nx\n");
+        std::fs::write(&release, "").unwrap();
+        wait_for(|| !detach.lifecycle.running.load(Ordering::SeqCst));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,240 @@
+//! Actions made of other actions, so that one gesture can do several things
+
+use super::command_action::Watch;
+use super::{Action, ActionError};
+use crate::common::ActionCondition;
+use crate::gesture_event::Context;
+
+use std::os::unix::process::CommandExt;
+use std::time::{Duration, Instant};
+
+/// How long condition commands can take before they are stopped and count as
+/// failed
+const CONDITION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Run the actions in order, stopping at the first that fails
+pub struct SequenceAction {
+    pub actions: Vec<Box<dyn Action>>,
+}
+
+/// Run all the actions, even when some fail. They are started one after
+/// another, so commands run at the same time unless they are waited for
+pub struct ParallelAction {
+    pub actions: Vec<Box<dyn Action>>,
+}
+
+pub struct IfAction {
+    pub condition: ActionCondition,
+    pub then: Box<dyn Action>,
+    pub otherwise: Box<dyn Action>,
+}
+
+impl Action for SequenceAction {
+    fn execute(&mut self, context: &Context) -> Result<(), ActionError> {
+        for action in &mut self.actions {
+            action.execute(context)?;
+        }
+        Ok(())
+    }
+
+    fn internal(&self) -> bool {
+        self.actions.iter().all(|a| a.internal())
+    }
+}
+
+impl Action for ParallelAction {
+    fn execute(&mut self, context: &Context) -> Result<(), ActionError> {
+        let errors = self
+            .actions
+            .iter_mut()
+            .filter_map(|a| a.execute(context).err())
+            .map(|e| e.0)
+            .collect::<Vec<_>>();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ActionError(errors.join("; ")))
+        }
+    }
+
+    fn internal(&self) -> bool {
+        self.actions.iter().all(|a| a.internal())
+    }
+}
+
+impl Action for IfAction {
+    fn execute(&mut self, context: &Context) -> Result<(), ActionError> {
+        let holds = holds(&self.condition, context)?;
+        log::debug!("Condition {:?} holds: {}", self.condition, holds);
+        if holds {
+            self.then.execute(context)
+        } else {
+            self.otherwise.execute(context)
+        }
+    }
+
+    // Checking the condition may run a command, so it's never internal
+}
+
+fn holds(condition: &ActionCondition, context: &Context) -> Result<bool, ActionError> {
+    Ok(match condition {
+        ActionCondition::Command(command) => {
+            let mut child = std::process::Command::new("/bin/sh")
+                .arg("-c")
+                .arg(command)
+                .envs(context.env())
+                .stdin(std::process::Stdio::null())
+                .env_remove("DBUS_SYSTEM_BUS_ADDRESS")
+                .process_group(0)
+                .spawn()?;
+            let watch = Watch {
+                deadline: Some(Instant::now() + CONDITION_TIMEOUT),
+                gesture: None,
+            };
+            watch.supervise(&mut child, command)?.success()
+        }
+        ActionCondition::Env(var) => std::env::var_os(var).is_some(),
+        ActionCondition::EnvEquals(var, value) => std::env::var(var).ok().as_ref() == Some(value),
+        ActionCondition::Window(_) if std::env::var_os("WAYLAND_DISPLAY").is_some() => {
+            return Err(ActionError(
+                "Window conditions only work on X11, not on Wayland".to_owned(),
+            ))
+        }
+        ActionCondition::Window(class) => focused_window_class()?
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(class)),
+        ActionCondition::All(cs) => {
+            for c in cs {
+                if !holds(c, context)? {
+                    return Ok(false);
+                }
+            }
+            true
+        }
+        ActionCondition::Any(cs) => {
+            for c in cs {
+                if holds(c, context)? {
+                    return Ok(true);
+                }
+            }
+            false
+        }
+        ActionCondition::Not(c) => !holds(c, context)?,
+    })
+}
+
+/// Instance and class names of the focused X11 window, empty when none is
+fn focused_window_class() -> Result<Vec<String>, ActionError> {
+    let xprop = |args: &[&str]| -> Result<String, ActionError> {
+        let output = std::process::Command::new("xprop")
+            .args(args)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .map_err(|e| ActionError(format!("running xprop: {}", e)))?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    // _NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007
+    let active = xprop(&["-root", "_NET_ACTIVE_WINDOW"])?;
+    let id = match active.split('#').nth(1).map(|s| s.trim()) {
+        Some(id) if !id.is_empty() && id != "0x0" => id.to_owned(),
+        _ => return Ok(Vec::new()),
+    };
+    Ok(parse_class(&xprop(&["-id", &id, "WM_CLASS"])?))
+}
+
+/// WM_CLASS(STRING) = "navigator", "firefox"
+fn parse_class(line: &str) -> Vec<String> {
+    match line.split_once('=') {
+        Some((_, names)) => names
+            .split(',')
+            .map(|n| n.trim().trim_matches('"').to_owned())
+            .filter(|n| !n.is_empty())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_class, IfAction, ParallelAction, SequenceAction};
+    use crate::action::{Action, ActionError};
+    use crate::common::ActionCondition;
+    use crate::gesture_event::Context;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Record {
+        log: Rc<RefCell<Vec<&'static str>>>,
+        name: &'static str,
+        fails: bool,
+    }
+
+    impl Action for Record {
+        fn execute(&mut self, _context: &Context) -> Result<(), ActionError> {
+            self.log.borrow_mut().push(self.name);
+            if self.fails {
+                Err(ActionError(self.name.to_owned()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn composite() {
+        let context = Context::default();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let record = |name, fails| -> Box<dyn Action> {
+            Box::new(Record {
+                log: log.clone(),
+                name,
+                fails,
+            })
+        };
+
+        let mut sequence = SequenceAction {
+            actions: vec![record("a", false), record("b", true), record("c", false)],
+        };
+        assert_eq!(sequence.execute(&context).unwrap_err().0, "b");
+        assert_eq!(*log.borrow(), ["a", "b"]);
+
+        log.borrow_mut().clear();
+        let mut parallel = ParallelAction {
+            actions: vec![record("a", true), record("b", false), record("c", true)],
+        };
+        assert_eq!(parallel.execute(&context).unwrap_err().0, "a; c");
+        assert_eq!(*log.borrow(), ["a", "b", "c"]);
+
+        log.borrow_mut().clear();
+        let condition = ActionCondition::All(vec![
+            ActionCondition::Command("true".to_owned()),
+            ActionCondition::Not(Box::new(ActionCondition::Env(
+                "WZMACH_SURELY_UNSET".to_owned(),
+            ))),
+        ]);
+        let mut choice = IfAction {
+            condition,
+            then: record("then", false),
+            otherwise: record("else", false),
+        };
+        choice.execute(&context).unwrap();
+        choice.condition = ActionCondition::Command("exit 3".to_owned());
+        choice.execute(&context).unwrap();
+        // slow conditions are stopped and don't hold
+        let start = std::time::Instant::now();
+        choice.condition = ActionCondition::Command("sleep 10".to_owned());
+        choice.execute(&context).unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(*log.borrow(), ["then", "else", "else"]);
+    }
+
+    #[test]
+    fn window_class() {
+        assert_eq!(
+            parse_class("WM_CLASS(STRING) = \"navigator\", \"firefox\"\n"),
+            ["navigator", "firefox"]
+        );
+        assert!(parse_class("WM_CLASS:  not found.\n").is_empty());
+    }
+}
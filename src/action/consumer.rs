@@ -1,18 +1,25 @@
 use super::{Action, ActionError};
+use crate::gesture_event::Fired;
 
 /// Iterator adaptor inteface for executing events. Now you can use a
 /// haskell-conduit like interface
-pub trait EventConsumerExt: Iterator<Item = Vec<usize>> {
+pub trait EventConsumerExt: Iterator<Item = Vec<Fired>> {
     fn consume_events(self, actions: &mut [Box<dyn Action>]);
 }
 
-impl<I: Iterator<Item = Vec<usize>>> EventConsumerExt for I {
+impl<I: Iterator<Item = Vec<Fired>>> EventConsumerExt for I {
     fn consume_events(self, actions: &mut [Box<dyn Action>]) {
-        for action_inds in self {
-            for index in action_inds {
-                match actions[index].execute() {
+        for fired in self {
+            for Fired {
+                index,
+                name,
+                context,
+            } in fired
+            {
+                log::debug!("Triggered {}", name);
+                match actions[index].execute(&context) {
                     Ok(()) => (),
-                    Err(ActionError(msg)) => log::error!("{}", msg),
+                    Err(ActionError(msg)) => log::error!("Trigger {}: {}", name, msg),
                 }
             }
         }
@@ -0,0 +1,245 @@
+use super::{Action, ActionError};
+use crate::common::{Bus, DBusValue};
+use crate::gesture_event::Context;
+
+use dbus::channel::{BusType, Channel};
+use dbus::Message;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// How long to wait for the method to return
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Connections to the buses, opened when first needed and shared by actions
+#[derive(Clone, Default)]
+pub struct Buses(Rc<RefCell<Connections>>);
+
+#[derive(Default)]
+struct Connections {
+    session: Option<Channel>,
+    system: Option<Channel>,
+}
+
+pub struct DBusAction {
+    pub buses: Buses,
+    pub bus: Bus,
+    pub destination: String,
+    pub path: String,
+    pub interface: String,
+    pub method: String,
+    pub args: Vec<DBusValue>,
+}
+
+impl Action for DBusAction {
+    fn execute(&mut self, _context: &Context) -> Result<(), ActionError> {
+        log::debug!(
+            "Call {}.{} on {} {} with {:?}",
+            self.interface,
+            self.method,
+            self.destination,
+            self.path,
+            self.args
+        );
+        let message = method_call(
+            &self.destination,
+            &self.path,
+            &self.interface,
+            &self.method,
+            &self.args,
+        )
+        .map_err(ActionError)?;
+        self.buses.call(self.bus, message)?;
+        Ok(())
+    }
+}
+
+/// Make the message, checking that the names are valid
+pub fn method_call(
+    destination: &str,
+    path: &str,
+    interface: &str,
+    method: &str,
+    args: &[DBusValue],
+) -> Result<Message, String> {
+    let mut message = Message::new_method_call(destination, path, interface, method)?;
+    for arg in args {
+        message = match *arg {
+            DBusValue::String(ref s) => message.append1(s),
+            DBusValue::ObjectPath(ref p) => message.append1(dbus::Path::new(p.as_str())?),
+            DBusValue::Boolean(x) => message.append1(x),
+            DBusValue::Byte(x) => message.append1(x),
+            DBusValue::Int16(x) => message.append1(x),
+            DBusValue::UInt16(x) => message.append1(x),
+            DBusValue::Int32(x) => message.append1(x),
+            DBusValue::UInt32(x) => message.append1(x),
+            DBusValue::Int64(x) => message.append1(x),
+            DBusValue::UInt64(x) => message.append1(x),
+            DBusValue::Double(x) => message.append1(x),
+        };
+    }
+    Ok(message)
+}
+
+impl Buses {
+    /// Call the method and wait for it to return
+    pub(super) fn call(&self, bus: Bus, message: Message) -> Result<Message, dbus::Error> {
+        let mut connections = self.0.borrow_mut();
+        let slot = match bus {
+            Bus::Session => &mut connections.session,
+            Bus::System => &mut connections.system,
+        };
+        let channel = match slot {
+            Some(channel) => channel,
+            None => slot.insert(connect(bus)?),
+        };
+        let reply = channel.send_with_reply_and_block(message, TIMEOUT);
+        if !channel.is_connected() {
+            log::debug!("Lost connection to {:?} bus", bus);
+            *slot = None;
+        }
+        reply
+    }
+}
+
+fn connect(bus: Bus) -> Result<Channel, dbus::Error> {
+    log::debug!("Connecting to {:?} bus", bus);
+    match bus {
+        // libdbus ignores the environment when wzmach is setgid, but the
+        // session bus is ours anyway
+        Bus::Session => match session_address(|name| std::env::var(name).ok()) {
+            Some(address) => open(&address),
+            None => Channel::get_private(BusType::Session),
+        },
+        Bus::System => Channel::get_private(BusType::System),
+    }
+}
+
+/// Address of the session bus from the environment
+fn session_address(env: impl Fn(&str) -> Option<String>) -> Option<String> {
+    env("DBUS_SESSION_BUS_ADDRESS")
+        .or_else(|| env("XDG_RUNTIME_DIR").map(|dir| format!("unix:path={}/bus", dir)))
+}
+
+fn open(address: &str) -> Result<Channel, dbus::Error> {
+    let mut channel = Channel::open_private(address)?;
+    channel.register()?;
+    Ok(channel)
+}
+
+impl From<dbus::Error> for ActionError {
+    fn from(err: dbus::Error) -> ActionError {
+        ActionError(format!("{}", err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{open, session_address, Buses, Connections, DBusAction};
+    use crate::action::Action;
+    use crate::common::{Bus, DBusValue};
+    use crate::gesture_event::Context;
+
+    use std::cell::RefCell;
+    use std::io::BufRead;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    #[test]
+    fn session() {
+        let env = |vars: &[(&str, &str)]| {
+            let vars = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>();
+            session_address(move |name| {
+                vars.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone())
+            })
+        };
+        assert_eq!(
+            env(&[
+                ("DBUS_SESSION_BUS_ADDRESS", "unix:path=/tmp/bus"),
+                ("XDG_RUNTIME_DIR", "/run/user/1000")
+            ]),
+            Some("unix:path=/tmp/bus".to_owned())
+        );
+        assert_eq!(
+            env(&[("XDG_RUNTIME_DIR", "/run/user/1000")]),
+            Some("unix:path=/run/user/1000/bus".to_owned())
+        );
+        assert_eq!(env(&[]), None);
+    }
+
+    #[test]
+    fn method_call() {
+        let mut daemon = match std::process::Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+        {
+            Ok(daemon) => daemon,
+            Err(e) => {
+                eprintln!("Skipping the test, can't start dbus-daemon: {}", e);
+                return;
+            }
+        };
+        let mut address = String::new();
+        std::io::BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        let address = address.trim().to_owned();
+
+        // The service answers one call and sends back what it got
+        let (name_tx, name_rx) = std::sync::mpsc::channel();
+        let service_address = address.clone();
+        let service = std::thread::spawn(move || {
+            let channel = open(&service_address).unwrap();
+            name_tx
+                .send(channel.unique_name().unwrap().to_owned())
+                .unwrap();
+            loop {
+                let message = channel
+                    .blocking_pop_message(Duration::from_secs(5))
+                    .unwrap()
+                    .expect("no call in time");
+                if message.member().as_deref() == Some("Hello") {
+                    channel.send(message.method_return()).unwrap();
+                    channel.flush();
+                    return (
+                        message.path().unwrap().to_string(),
+                        message.get2::<String, i32>(),
+                    );
+                }
+            }
+        });
+        let destination = name_rx.recv().unwrap();
+
+        // connected to the test bus instead of the one in the environment
+        let buses = Buses(Rc::new(RefCell::new(Connections {
+            session: Some(open(&address).unwrap()),
+            system: None,
+        })));
+        let mut action = DBusAction {
+            buses,
+            bus: Bus::Session,
+            destination,
+            path: "/org/wzmach/Test".to_owned(),
+            interface: "org.wzmach.Test".to_owned(),
+            method: "Hello".to_owned(),
+            args: vec![DBusValue::String("hi".to_owned()), DBusValue::Int32(3)],
+        };
+        let result = action.execute(&Context::default());
+        let received = service.join();
+        daemon.kill().unwrap();
+        daemon.wait().unwrap();
+
+        result.unwrap();
+        assert_eq!(
+            received.unwrap(),
+            (
+                "/org/wzmach/Test".to_owned(),
+                (Some("hi".to_owned()), Some(3))
+            )
+        );
+    }
+}
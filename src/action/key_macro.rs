@@ -0,0 +1,175 @@
+use super::uinput_action::EV_KEY;
+use super::{Action, ActionError};
+use crate::gesture_event::Context;
+
+use std::time::Duration;
+
+/// Keys pressed and released step by step, for programs that miss keys sent
+/// all at once
+pub struct KeyMacroAction {
+    pub device: std::rc::Rc<std::cell::RefCell<uinput::Device>>,
+    pub steps: Vec<KeyStep>,
+    /// Pause after each key step
+    pub delay: Duration,
+}
+
+/// Keys are evdev codes
+#[derive(PartialEq, Debug, Clone)]
+pub enum KeyStep {
+    Press(u16),
+    Release(u16),
+    Click(u16),
+    Delay(Duration),
+    Repeat(u32, Vec<KeyStep>),
+}
+
+/// What the macro does to the device, one thing at a time
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Output {
+    /// Key and whether it's pressed
+    Key(u16, bool),
+    Sync,
+    Wait(Duration),
+}
+
+impl Action for KeyMacroAction {
+    fn execute(&mut self, _context: &Context) -> Result<(), ActionError> {
+        let mut device = self.device.borrow_mut();
+        log::debug!("Execute macro {:?}", self.steps);
+        let mut send = |output| match output {
+            Output::Key(key, pressed) => device.write(EV_KEY, key.into(), pressed as i32),
+            Output::Sync => device.synchronize(),
+            Output::Wait(time) => {
+                std::thread::sleep(time);
+                Ok(())
+            }
+        };
+        Ok(play(&mut send, &self.steps, self.delay)?)
+    }
+}
+
+/// Run the steps, then release the keys still held, whether the macro failed
+/// or forgot to release them. Like with UinputAction, keys don't get stuck
+fn play<E>(
+    send: &mut impl FnMut(Output) -> Result<(), E>,
+    steps: &[KeyStep],
+    delay: Duration,
+) -> Result<(), E> {
+    let mut pressed = Vec::new();
+    let done = run(send, steps, delay, &mut pressed);
+    for key in pressed.into_iter().rev() {
+        log::debug!("Releasing key {} left pressed by the macro", key);
+        send(Output::Key(key, false))?;
+    }
+    send(Output::Sync)?;
+    done
+}
+
+fn run<E>(
+    send: &mut impl FnMut(Output) -> Result<(), E>,
+    steps: &[KeyStep],
+    delay: Duration,
+    pressed: &mut Vec<u16>,
+) -> Result<(), E> {
+    for step in steps {
+        match *step {
+            KeyStep::Press(key) => {
+                send(Output::Key(key, true))?;
+                pressed.push(key);
+            }
+            KeyStep::Release(key) => {
+                send(Output::Key(key, false))?;
+                pressed.retain(|&k| k != key);
+            }
+            KeyStep::Click(key) => {
+                send(Output::Key(key, true))?;
+                send(Output::Sync)?;
+                send(Output::Key(key, false))?;
+            }
+            KeyStep::Delay(time) => {
+                send(Output::Wait(time))?;
+                continue;
+            }
+            KeyStep::Repeat(times, ref steps) => {
+                for _ in 0..times {
+                    run(send, steps, delay, pressed)?;
+                }
+                continue;
+            }
+        }
+        send(Output::Sync)?;
+        send(Output::Wait(delay))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{play, KeyStep, Output};
+
+    use std::time::Duration;
+
+    #[test]
+    fn steps() {
+        let ms = Duration::from_millis;
+        let steps = [
+            KeyStep::Press(42),
+            KeyStep::Repeat(2, vec![KeyStep::Click(30)]),
+            KeyStep::Delay(ms(100)),
+            KeyStep::Release(42),
+        ];
+        let mut sent = Vec::new();
+        play(
+            &mut |o| -> Result<(), ()> {
+                sent.push(o);
+                Ok(())
+            },
+            &steps,
+            ms(10),
+        )
+        .unwrap();
+        let click = [
+            Output::Key(30, true),
+            Output::Sync,
+            Output::Key(30, false),
+            Output::Sync,
+            Output::Wait(ms(10)),
+        ];
+        let mut expected = vec![Output::Key(42, true), Output::Sync, Output::Wait(ms(10))];
+        expected.extend(click);
+        expected.extend(click);
+        expected.extend([
+            Output::Wait(ms(100)),
+            Output::Key(42, false),
+            Output::Sync,
+            Output::Wait(ms(10)),
+            Output::Sync,
+        ]);
+        assert_eq!(sent, expected);
+
+        // the device fails on the click, and shift is released anyway
+        let mut sent = Vec::new();
+        let failed = play(
+            &mut |o| {
+                if o == Output::Key(30, true) {
+                    return Err("gone");
+                }
+                sent.push(o);
+                Ok(())
+            },
+            &steps,
+            ms(10),
+        );
+        assert_eq!(failed, Err("gone"));
+        assert_eq!(
+            sent,
+            [
+                Output::Key(42, true),
+                Output::Sync,
+                Output::Wait(ms(10)),
+                Output::Key(42, false),
+                Output::Sync,
+            ]
+        );
+    }
+}
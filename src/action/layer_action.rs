@@ -0,0 +1,22 @@
+use super::{Action, ActionError};
+use crate::common::LayerMode;
+use crate::gesture_event::layer::Layers;
+use crate::gesture_event::Context;
+
+pub struct SwitchLayerAction {
+    pub layers: Layers,
+    pub layer: String,
+    pub mode: LayerMode,
+}
+
+impl Action for SwitchLayerAction {
+    fn execute(&mut self, _context: &Context) -> Result<(), ActionError> {
+        log::debug!("Switch to layer {} ({:?})", self.layer, self.mode);
+        self.layers.switch(&self.layer, self.mode);
+        Ok(())
+    }
+
+    fn internal(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,183 @@
+//! Media control over MPRIS, the D-Bus interface that music and video players
+//! implement. This works without media keys being set up in the desktop.
+
+use super::dbus_action::Buses;
+use super::{Action, ActionError};
+use crate::common::{Bus, MediaCommand};
+use crate::gesture_event::Context;
+
+use dbus::arg::Variant;
+use dbus::Message;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const PLAYER_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const PLAYER_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// The player that media actions controlled last, shared by them
+#[derive(Clone, Default)]
+pub struct LastPlayer(Rc<RefCell<Option<String>>>);
+
+pub struct MediaAction {
+    pub buses: Buses,
+    pub last: LastPlayer,
+    pub command: MediaCommand,
+    /// Name of the player, like spotify or vlc. Default: the active one
+    pub player: Option<String>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Status {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+impl Action for MediaAction {
+    fn execute(&mut self, _context: &Context) -> Result<(), ActionError> {
+        let player = self.find_player()?;
+        log::debug!("Media {:?} on {}", self.command, player);
+        let call = |method: &str| {
+            Message::new_method_call(&player, PLAYER_PATH, PLAYER_INTERFACE, method)
+                .map_err(ActionError)
+        };
+        let message = match self.command {
+            MediaCommand::PlayPause => call("PlayPause")?,
+            MediaCommand::Next => call("Next")?,
+            MediaCommand::Previous => call("Previous")?,
+            MediaCommand::Seek(secs) => call("Seek")?.append1((secs * 1e6) as i64),
+            MediaCommand::Volume(delta) => {
+                let volume = self.get::<f64>(&player, "Volume")?;
+                let volume = (volume + delta).clamp(0.0, 1.0);
+                properties(&player, "Set")?.append3(PLAYER_INTERFACE, "Volume", Variant(volume))
+            }
+        };
+        self.buses.call(Bus::Session, message)?;
+        *self.last.0.borrow_mut() = Some(player);
+        Ok(())
+    }
+}
+
+impl MediaAction {
+    fn find_player(&self) -> Result<String, ActionError> {
+        let list = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "ListNames",
+        )
+        .map_err(ActionError)?;
+        let names = self
+            .buses
+            .call(Bus::Session, list)?
+            .read1::<Vec<String>>()
+            .map_err(|e| ActionError(format!("{}", e)))?;
+        let players = names
+            .into_iter()
+            .filter(|name| is_player(name, self.player.as_deref()))
+            .map(|name| {
+                let status = match self.get::<String>(&name, "PlaybackStatus") {
+                    Ok(s) if s == "Playing" => Status::Playing,
+                    Ok(s) if s == "Paused" => Status::Paused,
+                    _ => Status::Stopped,
+                };
+                (name, status)
+            })
+            .collect::<Vec<_>>();
+        choose(&players, self.last.0.borrow().as_deref()).ok_or_else(|| {
+            ActionError(match self.player {
+                Some(ref p) => format!("media player {} is not running", p),
+                None => "no media player is running".to_owned(),
+            })
+        })
+    }
+
+    /// Read a property of the player
+    fn get<T>(&self, player: &str, property: &str) -> Result<T, ActionError>
+    where
+        T: for<'a> dbus::arg::Get<'a> + 'static,
+    {
+        let message = properties(player, "Get")?.append2(PLAYER_INTERFACE, property);
+        self.buses
+            .call(Bus::Session, message)?
+            .read1::<Variant<T>>()
+            .map(|v| v.0)
+            .map_err(|e| ActionError(format!("{}: {}", property, e)))
+    }
+}
+
+fn properties(player: &str, method: &str) -> Result<Message, ActionError> {
+    Message::new_method_call(player, PLAYER_PATH, PROPERTIES_INTERFACE, method).map_err(ActionError)
+}
+
+/// Players can have an instance suffix, like org.mpris.MediaPlayer2.vlc.instance42
+fn is_player(name: &str, wanted: Option<&str>) -> bool {
+    match (name.strip_prefix(PLAYER_PREFIX), wanted) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(player), Some(wanted)) => {
+            player == wanted
+                || player
+                    .strip_prefix(wanted)
+                    .is_some_and(|rest| rest.starts_with('.'))
+        }
+    }
+}
+
+/// The player that plays now, preferring the one controlled last. Otherwise
+/// the one controlled last, then a paused one, then any
+fn choose(players: &[(String, Status)], last: Option<&str>) -> Option<String> {
+    let is_last = |name: &str| Some(name) == last;
+    let rank = |(name, status): &(String, Status)| {
+        (
+            *status == Status::Playing,
+            is_last(name),
+            *status == Status::Paused,
+        )
+    };
+    // max_by_key takes the last of equals, keep the first one instead
+    players
+        .iter()
+        .rev()
+        .max_by_key(|p| rank(p))
+        .map(|(name, _)| name.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{choose, is_player, Status};
+
+    #[test]
+    fn players() {
+        assert!(is_player(
+            "org.mpris.MediaPlayer2.vlc.instance42",
+            Some("vlc")
+        ));
+        assert!(!is_player("org.mpris.MediaPlayer2.vlcx", Some("vlc")));
+        assert!(is_player("org.mpris.MediaPlayer2.spotify", None));
+        assert!(!is_player("org.kde.KWin", None));
+
+        let players = |statuses: &[(&str, Status)]| {
+            statuses
+                .iter()
+                .map(|(n, s)| (n.to_string(), *s))
+                .collect::<Vec<_>>()
+        };
+        let ps = players(&[
+            ("a", Status::Stopped),
+            ("b", Status::Paused),
+            ("c", Status::Playing),
+        ]);
+        assert_eq!(choose(&ps, Some("a")), Some("c".to_owned()));
+        let ps = players(&[
+            ("a", Status::Paused),
+            ("b", Status::Stopped),
+            ("c", Status::Paused),
+        ]);
+        assert_eq!(choose(&ps, Some("b")), Some("b".to_owned()));
+        assert_eq!(choose(&ps, None), Some("a".to_owned()));
+        assert_eq!(choose(&[], None), None);
+    }
+}
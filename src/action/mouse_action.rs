@@ -0,0 +1,143 @@
+use super::uinput_action::{create_device, EV_KEY, EV_REL};
+use super::{Action, ActionError};
+use crate::common::MouseButton;
+use crate::error::Error;
+use crate::gesture_event::Context;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Name of the virtual mouse that actions click and scroll with, so that mouse
+/// gestures don't take it for a real one
+pub const VIRTUAL_POINTER: &str = "wzmach_pointer";
+
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+const REL_WHEEL_HI_RES: u16 = 0x0b;
+const REL_HWHEEL_HI_RES: u16 = 0x0c;
+
+/// High resolution scroll is in 120ths of a wheel detent
+const WHEEL_DETENT: i32 = 120;
+
+/// Create the virtual mouse. It's only created when some action needs it, as
+/// desktops treat touchpads differently when a mouse is plugged in
+pub fn pointer_device() -> Result<Rc<RefCell<uinput::Device>>, Error> {
+    let buttons = [
+        MouseButton::Left,
+        MouseButton::Right,
+        MouseButton::Middle,
+        MouseButton::Back,
+        MouseButton::Forward,
+    ]
+    .map(|b| b.code() as u16);
+    let device = create_mouse(VIRTUAL_POINTER, &buttons)?;
+    log::debug!("Created virtual pointer");
+    Ok(Rc::new(RefCell::new(device)))
+}
+
+/// Virtual mouse with the buttons, motion, and both kinds of scroll wheels
+pub fn create_mouse(name: &str, buttons: &[u16]) -> Result<uinput::Device, Error> {
+    let rels = [
+        REL_X,
+        REL_Y,
+        REL_WHEEL,
+        REL_HWHEEL,
+        REL_WHEEL_HI_RES,
+        REL_HWHEEL_HI_RES,
+    ];
+    create_device(name, buttons, &rels)
+}
+
+/// Scroll by 120ths of a detent, positive up and right like evdev. Programs
+/// that know high resolution scrolling get it as is, the rest get whole
+/// detents once they add up in the remainder
+pub fn send_scroll(
+    device: &mut uinput::Device,
+    vertical: i32,
+    horizontal: i32,
+    remainder: &mut (i32, i32),
+) -> Result<(), uinput::Error> {
+    for (value, remainder, hi_res, detents) in [
+        (vertical, &mut remainder.0, REL_WHEEL_HI_RES, REL_WHEEL),
+        (horizontal, &mut remainder.1, REL_HWHEEL_HI_RES, REL_HWHEEL),
+    ] {
+        if value == 0 {
+            continue;
+        }
+        device.write(EV_REL, hi_res.into(), value)?;
+        *remainder += value;
+        let whole = *remainder / WHEEL_DETENT;
+        if whole != 0 {
+            device.write(EV_REL, detents.into(), whole)?;
+            *remainder -= whole * WHEEL_DETENT;
+        }
+    }
+    Ok(())
+}
+
+pub struct MouseClickAction {
+    pub device: Rc<RefCell<uinput::Device>>,
+    pub button: MouseButton,
+}
+
+impl Action for MouseClickAction {
+    fn execute(&mut self, _context: &Context) -> Result<(), ActionError> {
+        let mut device = self.device.borrow_mut();
+        log::debug!("Click {:?} mouse button", self.button);
+        let code = self.button.code() as i32;
+        device.write(EV_KEY, code, 1)?;
+        device.synchronize()?;
+        device.write(EV_KEY, code, 0)?;
+        device.synchronize()?;
+        Ok(())
+    }
+}
+
+/// Scroll by wheel detents. Programs that know high resolution scrolling get
+/// fractions of detents, the rest get whole detents once they add up
+pub struct ScrollAction {
+    pub device: Rc<RefCell<uinput::Device>>,
+    /// Positive scrolls down
+    pub vertical: f64,
+    /// Positive scrolls right
+    pub horizontal: f64,
+    /// High resolution scroll not yet sent as whole detents
+    pub remainder: (i32, i32),
+}
+
+impl Action for ScrollAction {
+    fn execute(&mut self, _context: &Context) -> Result<(), ActionError> {
+        let mut device = self.device.borrow_mut();
+        log::debug!("Scroll by {} and {}", self.vertical, self.horizontal);
+        // evdev scrolls up with positive values
+        let v = -(self.vertical * WHEEL_DETENT as f64).round() as i32;
+        let h = (self.horizontal * WHEEL_DETENT as f64).round() as i32;
+        send_scroll(&mut device, v, h, &mut self.remainder)?;
+        device.synchronize()?;
+        Ok(())
+    }
+}
+
+/// Move the pointer, in the same units as mouse movement
+pub struct PointerMotionAction {
+    pub device: Rc<RefCell<uinput::Device>>,
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Action for PointerMotionAction {
+    fn execute(&mut self, _context: &Context) -> Result<(), ActionError> {
+        let mut device = self.device.borrow_mut();
+        log::debug!("Move pointer by {}, {}", self.x, self.y);
+        if self.x != 0 {
+            device.write(EV_REL, REL_X.into(), self.x)?;
+        }
+        if self.y != 0 {
+            device.write(EV_REL, REL_Y.into(), self.y)?;
+        }
+        device.synchronize()?;
+        Ok(())
+    }
+}
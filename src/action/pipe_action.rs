@@ -0,0 +1,174 @@
+//! Lines written to a helper process that keeps running between gestures, so
+//! that it starts once instead of on every trigger and can keep its state
+
+use super::command_action::{log_stderr, stop};
+use super::{Action, ActionError};
+use crate::gesture_event::Context;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStdin};
+use std::rc::Rc;
+
+/// Running helpers by their command, started when first needed and shared by
+/// the actions with the same command
+#[derive(Clone, Default)]
+pub struct Pipes(Rc<RefCell<HashMap<String, Helper>>>);
+
+struct Helper {
+    child: Child,
+    stdin: ChildStdin,
+}
+
+pub struct PipeAction {
+    pub pipes: Pipes,
+    /// Shell command of the helper
+    pub command: String,
+    /// Line to write, with placeholders like {fingers}
+    pub line: String,
+}
+
+impl Action for PipeAction {
+    fn execute(&mut self, context: &Context) -> Result<(), ActionError> {
+        let line = context.substitute(&self.line);
+        log::debug!("Write {:?} to {}", line, self.command);
+        self.pipes.write(&self.command, &line)
+    }
+}
+
+impl Pipes {
+    fn write(&self, command: &str, line: &str) -> Result<(), ActionError> {
+        let mut helpers = self.0.borrow_mut();
+        if let Some(mut helper) = helpers.remove(command) {
+            match helper.child.try_wait()? {
+                None => match send(&mut helper.stdin, line) {
+                    Ok(()) => {
+                        helpers.insert(command.to_owned(), helper);
+                        return Ok(());
+                    }
+                    // Its stdin is full, closed, or it exited since we
+                    // checked. Reap it before starting another one
+                    Err(e) => {
+                        log::warn!("Helper {} stopped reading: {}", command, e);
+                        stop(&mut helper.child)?;
+                    }
+                },
+                Some(status) => log::warn!("Helper {} exited with {}", command, status),
+            }
+        }
+        let mut helper = start(command)?;
+        send(&mut helper.stdin, line)?;
+        helpers.insert(command.to_owned(), helper);
+        Ok(())
+    }
+}
+
+fn start(command: &str) -> Result<Helper, ActionError> {
+    log::info!("Starting helper {}", command);
+    let mut child = std::process::Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::piped())
+        .env_remove("DBUS_SYSTEM_BUS_ADDRESS")
+        // Ctrl+C on wzmach in a terminal shouldn't kill the helper first
+        .process_group(0)
+        .spawn()?;
+    log_stderr(&mut child, command);
+    let stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| ActionError(format!("no stdin for helper {}", command)))?;
+    // A helper that stops reading must not stop the gestures, so writing
+    // fails instead of waiting when its pipe is full
+    use nix::fcntl::{fcntl, FcntlArg, OFlag};
+    let fd = stdin.as_raw_fd();
+    fcntl(fd, FcntlArg::F_GETFL)
+        .and_then(|flags| {
+            let flags = OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK;
+            fcntl(fd, FcntlArg::F_SETFL(flags))
+        })
+        .map_err(|e| ActionError(format!("helper {}: {}", command, e)))?;
+    Ok(Helper { child, stdin })
+}
+
+/// Write the line in one go, failing with WouldBlock when it doesn't fit
+fn send(stdin: &mut ChildStdin, line: &str) -> std::io::Result<()> {
+    stdin.write_all(format!("{}\n", line).as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PipeAction, Pipes};
+    use crate::action::command_action::stop;
+    use crate::action::test::{temp_path, wait_for};
+    use crate::action::Action;
+    use crate::gesture_event::Context;
+
+    #[test]
+    fn restart() {
+        let path = temp_path("pipe");
+        let written = || std::fs::read_to_string(&path).unwrap_or_default();
+        let context = |trigger: &str| Context {
+            trigger: trigger.to_owned(),
+            fingers: Some(3),
+            ..Context::default()
+        };
+
+        // the helper exits after one line, so it's restarted for the next one
+        let pipes = Pipes::default();
+        let command = format!("head -n 1 >> {}", path.display());
+        let mut action = PipeAction {
+            pipes: pipes.clone(),
+            command: command.clone(),
+            line: "{trigger} {fingers}".to_owned(),
+        };
+        action.execute(&context("first")).unwrap();
+        wait_for(|| {
+            let mut helpers = pipes.0.borrow_mut();
+            let helper = helpers.get_mut(&command).unwrap();
+            helper.child.try_wait().unwrap().is_some()
+        });
+        action.execute(&context("second")).unwrap();
+        wait_for(|| written() == "first 3\nsecond 3\n");
+
+        // this one keeps running but stops reading, so the line only fails
+        // to be written and it's stopped and replaced
+        std::fs::remove_file(&path).unwrap();
+        action.command = format!(
+            "read line; exec <&-; echo \"$line\" >> {}; sleep 10",
+            path.display()
+        );
+        action.execute(&context("third")).unwrap();
+        wait_for(|| written() == "third 3\n");
+        action.execute(&context("fourth")).unwrap();
+        wait_for(|| written() == "third 3\nfourth 3\n");
+
+        // this one keeps its stdin open but stops reading, so its pipe fills
+        // up and it's stopped and replaced instead of blocking
+        std::fs::remove_file(&path).unwrap();
+        action.command = format!("read line; echo \"$line\" >> {}; sleep 10", path.display());
+        let wedged_command = action.command.clone();
+        let pid = || pipes.0.borrow()[&wedged_command].child.id();
+        action.execute(&context("fifth")).unwrap();
+        wait_for(|| written() == "fifth 3\n");
+        let wedged = pid();
+        for _ in 0..100_000 {
+            action.execute(&context("next")).unwrap();
+            if pid() != wedged {
+                break;
+            }
+        }
+        assert_ne!(pid(), wedged);
+        wait_for(|| written() == "fifth 3\nnext 3\n");
+
+        for (_, mut helper) in pipes.0.borrow_mut().drain() {
+            stop(&mut helper.child).unwrap();
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+//! Commands sent to sway or i3 over their IPC socket, like swaymsg does
+
+use super::{Action, ActionError};
+use crate::gesture_event::Context;
+
+use serde::Deserialize;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+const MAGIC: &[u8] = b"i3-ipc";
+const RUN_COMMAND: u32 = 0;
+
+/// How long to wait for the reply
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct SwayAction {
+    /// Command like in the sway config, several can be separated with ;
+    pub command: String,
+}
+
+/// Reply for one of the commands
+#[derive(Deserialize)]
+struct Outcome {
+    success: bool,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+impl Action for SwayAction {
+    fn execute(&mut self, _context: &Context) -> Result<(), ActionError> {
+        log::debug!("Run sway command {:?}", self.command);
+        let socket = std::env::var_os("SWAYSOCK")
+            .or_else(|| std::env::var_os("I3SOCK"))
+            .ok_or_else(|| ActionError("neither SWAYSOCK nor I3SOCK is set".to_owned()))?;
+        run_command(Path::new(&socket), &self.command)
+    }
+}
+
+fn run_command(socket: &Path, command: &str) -> Result<(), ActionError> {
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|e| ActionError(format!("connecting to {}: {}", socket.display(), e)))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.write_all(&message(RUN_COMMAND, command.as_bytes()))?;
+
+    let mut header = [0; 14];
+    stream.read_exact(&mut header)?;
+    if &header[..6] != MAGIC {
+        return Err(ActionError("not an i3 IPC reply".to_owned()));
+    }
+    let length = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+    let mut payload = vec![0; length as usize];
+    stream.read_exact(&mut payload)?;
+    let outcomes = serde_json::from_slice::<Vec<Outcome>>(&payload)
+        .map_err(|e| ActionError(format!("bad reply to {:?}: {}", command, e)))?;
+    let errors = outcomes
+        .into_iter()
+        .filter(|o| !o.success)
+        .map(|o| o.error.unwrap_or_else(|| "unknown error".to_owned()))
+        .collect::<Vec<_>>();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ActionError(format!("{:?}: {}", command, errors.join("; "))))
+    }
+}
+
+/// Magic string, payload length and message type in native byte order, then
+/// the payload
+fn message(kind: u32, payload: &[u8]) -> Vec<u8> {
+    let mut message = MAGIC.to_vec();
+    message.extend((payload.len() as u32).to_ne_bytes());
+    message.extend(kind.to_ne_bytes());
+    message.extend(payload);
+    message
+}
+
+#[cfg(test)]
+mod test {
+    use super::{message, run_command, RUN_COMMAND};
+    use crate::action::test::temp_path;
+
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn run_commands() {
+        let path = temp_path("sway");
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        // The fake sway fails commands that mention "nope"
+        let server = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut header = [0; 14];
+                stream.read_exact(&mut header).unwrap();
+                let length = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+                let kind = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+                let mut payload = vec![0; length as usize];
+                stream.read_exact(&mut payload).unwrap();
+                let command = String::from_utf8(payload).unwrap();
+                let reply = if command.contains("nope") {
+                    r#"[{"success":true},{"success":false,"parse_error":true,"error":"Unknown command 'nope'"}]"#
+                } else {
+                    r#"[{"success":true}]"#
+                };
+                stream
+                    .write_all(&message(RUN_COMMAND, reply.as_bytes()))
+                    .unwrap();
+                received.push((kind, command));
+            }
+            received
+        });
+
+        let ok = run_command(&path, "workspace next");
+        let failed = run_command(&path, "workspace next; nope");
+        let received = server.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        ok.unwrap();
+        assert_eq!(
+            failed.unwrap_err().0,
+            "\"workspace next; nope\": Unknown command 'nope'"
+        );
+        assert_eq!(
+            received,
+            [
+                (RUN_COMMAND, "workspace next".to_owned()),
+                (RUN_COMMAND, "workspace next; nope".to_owned())
+            ]
+        );
+    }
+}
@@ -1,41 +1,153 @@
 use super::{Action, ActionError};
-use uinput::event::keyboard::Keyboard;
+use crate::error::Error;
+use crate::gesture_event::Context;
+
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+
+const UINPUT_PATH: &str = "/dev/uinput";
+
+pub(super) const EV_KEY: i32 = 0x01;
+pub(super) const EV_REL: i32 = 0x02;
+const KEY_MAX: u16 = 0x2ff;
+const BUS_VIRTUAL: u16 = 0x06;
+
+nix::ioctl_write_int!(ui_set_evbit, b'U', 100);
+nix::ioctl_write_int!(ui_set_keybit, b'U', 101);
+nix::ioctl_write_int!(ui_set_relbit, b'U', 102);
+nix::ioctl_none!(ui_dev_create, b'U', 1);
+
+/// struct uinput_user_dev from linux/uinput.h
+#[repr(C)]
+struct UserDev {
+    name: [u8; 80],
+    /// Bus, vendor, product and version
+    id: [u16; 4],
+    ff_effects_max: u32,
+    /// Absolute axes: max, min, fuzz and flat
+    abs: [[i32; 64]; 4],
+}
+
+/// Name of the virtual keyboard that actions type with
+pub const VIRTUAL_KEYBOARD: &str = "wzmach_virtual";
 
 pub struct UinputAction {
     pub device: std::rc::Rc<std::cell::RefCell<uinput::Device>>,
-    pub modifiers: Vec<Keyboard>,
-    pub sequence: Vec<Keyboard>,
+    /// Typed one after another
+    pub chords: Vec<KeyChord>,
+}
+
+/// Keys clicked one at a time while the modifiers are held, as evdev codes
+#[derive(PartialEq, Debug)]
+pub struct KeyChord {
+    pub modifiers: Vec<u16>,
+    pub sequence: Vec<u16>,
 }
 
 impl UinputAction {
-    pub fn default_device() -> std::rc::Rc<std::cell::RefCell<uinput::Device>> {
-        let device = uinput::open("/dev/uinput")
-            .unwrap()
-            .name("wzmach_virtual")
-            .unwrap()
-            .event(uinput::event::Keyboard::All)
-            .unwrap()
-            .create()
-            .unwrap();
+    /// Virtual keyboard with every key
+    pub fn default_device() -> Result<std::rc::Rc<std::cell::RefCell<uinput::Device>>, Error> {
+        let keys = (0..=KEY_MAX)
+            .filter(|&c| is_key_code(c))
+            .collect::<Vec<_>>();
+        let device = create_device(VIRTUAL_KEYBOARD, &keys, &[])?;
         log::debug!("Created uinput device");
-        std::rc::Rc::new(std::cell::RefCell::new(device))
+        Ok(std::rc::Rc::new(std::cell::RefCell::new(device)))
     }
 }
 
+/// Virtual device with the given key and relative axis codes. The uinput crate
+/// only knows some of them, so the device is set up here
+pub(super) fn create_device(
+    name: &str,
+    keys: &[u16],
+    rels: &[u16],
+) -> Result<uinput::Device, Error> {
+    let mut file = open_file()?;
+    let fd = file.as_raw_fd();
+    let mut dev = UserDev {
+        name: [0; 80],
+        id: [BUS_VIRTUAL, 0, 0, 1],
+        ff_effects_max: 0,
+        abs: [[0; 64]; 4],
+    };
+    dev.name[..name.len()].copy_from_slice(name.as_bytes());
+    // SAFETY: the struct is plain data laid out like the C one
+    let bytes = unsafe {
+        std::slice::from_raw_parts(
+            &dev as *const UserDev as *const u8,
+            std::mem::size_of::<UserDev>(),
+        )
+    };
+    let set_up = (|| -> Result<(), std::io::Error> {
+        // SAFETY: the ioctls only take integers
+        unsafe {
+            ui_set_evbit(fd, EV_KEY as u64)?;
+            for &code in keys {
+                ui_set_keybit(fd, code as u64)?;
+            }
+            if !rels.is_empty() {
+                ui_set_evbit(fd, EV_REL as u64)?;
+            }
+            for &code in rels {
+                ui_set_relbit(fd, code as u64)?;
+            }
+        }
+        file.write_all(bytes)?;
+        unsafe { ui_dev_create(fd) }?;
+        Ok(())
+    })();
+    set_up.map_err(|e| Error::Uinput(format!("{}", e)))?;
+    Ok(uinput::Device::new(file.into_raw_fd()))
+}
+
+/// Keys the virtual keyboard has: all of them but the mouse, joystick and
+/// other buttons, so that it's not taken for one of those devices
+pub fn is_key_code(code: u16) -> bool {
+    matches!(code, 1..=0xff | 0x160..=0x21f | 0x224..=0x2bf)
+}
+
+/// Open uinput for writing. uinput reports errors from an old nix version, so
+/// we can't look into them. Check the common failure with the std instead
+fn open_file() -> Result<std::fs::File, Error> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(UINPUT_PATH)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::PermissionDenied => Error::PermissionDenied(UINPUT_PATH.to_owned()),
+            _ => Error::Uinput(format!("{}: {}", UINPUT_PATH, e)),
+        })
+}
+
 impl Action for UinputAction {
-    fn execute(&mut self) -> Result<(), ActionError> {
+    fn execute(&mut self, _context: &Context) -> Result<(), ActionError> {
         let mut device = self.device.borrow_mut();
-        log::debug!("Execute action {:?} + {:?}", self.modifiers, self.sequence);
-        for modifier in &self.modifiers {
-            device.press(modifier)?;
-        }
-        for key in &self.sequence {
-            device.click(key)?;
-        }
-        for modifier in self.modifiers.iter().rev() {
-            device.release(modifier)?;
+        for chord in &self.chords {
+            log::debug!(
+                "Execute action {:?} + {:?}",
+                chord.modifiers,
+                chord.sequence
+            );
+            let mut pressed = Vec::new();
+            let clicked = (|| -> Result<(), uinput::Error> {
+                for &modifier in &chord.modifiers {
+                    device.write(EV_KEY, modifier.into(), 1)?;
+                    pressed.push(modifier);
+                }
+                for &key in &chord.sequence {
+                    device.write(EV_KEY, key.into(), 1)?;
+                    device.write(EV_KEY, key.into(), 0)?;
+                }
+                Ok(())
+            })();
+            // Release what we pressed even if something failed in the middle,
+            // or the modifiers stay stuck until the user presses them again
+            for modifier in pressed.into_iter().rev() {
+                device.write(EV_KEY, modifier.into(), 0)?;
+            }
+            device.synchronize()?;
+            clicked?;
         }
-        device.synchronize()?;
         Ok(())
     }
 }
@@ -28,3 +28,101 @@ pub enum AnyDirection {
     Pinch(PinchDirection),
     Rotate(RotateDirection),
 }
+
+/// How long a layer stays active after switching to it
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize)]
+pub enum LayerMode {
+    /// Until switched again. Switching to the active layer goes back to base
+    Toggle,
+    /// Until the current gesture ends
+    UntilGestureEnd,
+    /// For this many milliseconds
+    Timeout(u64),
+}
+
+/// Mouse buttons that can be used for gestures and sent by actions. Back and
+/// Forward are the side buttons that browsers use for navigation
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Back,
+    Forward,
+}
+
+impl MouseButton {
+    /// Button code as libinput and evdev see it
+    pub fn code(&self) -> u32 {
+        match self {
+            MouseButton::Left => 0x110,
+            MouseButton::Right => 0x111,
+            MouseButton::Middle => 0x112,
+            MouseButton::Back => 0x113,
+            MouseButton::Forward => 0x114,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Deserialize)]
+pub enum Bus {
+    #[default]
+    Session,
+    System,
+}
+
+/// Argument of a D-Bus method call, with its D-Bus type
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+pub enum DBusValue {
+    String(String),
+    ObjectPath(String),
+    Boolean(bool),
+    Byte(u8),
+    Int16(i16),
+    UInt16(u16),
+    Int32(i32),
+    UInt32(u32),
+    Int64(i64),
+    UInt64(u64),
+    Double(f64),
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
+pub enum MediaCommand {
+    PlayPause,
+    Next,
+    Previous,
+    /// Seconds forward, negative to go back
+    Seek(f64),
+    /// Change of volume, where 1.0 is full volume
+    Volume(f64),
+}
+
+/// Checked each time an If action runs
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+pub enum ActionCondition {
+    /// Shell command exits successfully
+    Command(String),
+    /// Environment variable is set
+    Env(String),
+    /// Environment variable is set to this value
+    EnvEquals(String, String),
+    /// Class of the focused window, like firefox. Only on X11, with xprop, and
+    /// an error on Wayland
+    Window(String),
+    All(Vec<ActionCondition>),
+    Any(Vec<ActionCondition>),
+    Not(Box<ActionCondition>),
+}
+
+/// What command actions do about the commands they started
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Deserialize)]
+pub enum CommandMode {
+    /// Start the command and go on
+    #[default]
+    Detach,
+    /// Wait for the command to finish, and fail if it does
+    Wait,
+    /// Don't start the command while it's still running from before
+    Singleton,
+}
@@ -1,29 +1,81 @@
+mod check;
+mod condition;
 mod key;
+mod keymap;
 mod trigger;
 
 use crate::action;
+use crate::common::{
+    ActionCondition, Bus, CommandMode, DBusValue, LayerMode, MediaCommand, MouseButton,
+};
+use crate::error::Error;
+use crate::gesture_event::layer::Layers;
+use crate::gesture_event::shape::{Shapes, Template};
 use crate::gesture_event::trigger as gesture;
+use crate::gesture_event::GestureCount;
+use condition::Condition;
 use trigger::Trigger;
 
 use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
 
 #[derive(PartialEq, Debug, Default, Deserialize)]
 pub struct Config {
+    /// Other config files to read before this one, relative to it. This file
+    /// adds to them and overrides them
+    #[serde(default)]
+    include: Vec<String>,
+
     /// Distance for fingers to travel to trigger. Default: 100
-    #[serde(default = "default_distance")]
-    swipe_distance: u32,
+    #[serde(default, deserialize_with = "some")]
+    swipe_distance: Option<u32>,
 
     /// Distance for fingers to travel to trigger. Default: 100
-    #[serde(default = "default_distance")]
-    shear_distance: u32,
+    #[serde(default, deserialize_with = "some")]
+    shear_distance: Option<u32>,
 
     /// Scale to achieve to trigger. Default: 1.4
-    #[serde(default = "default_pinch")]
-    pinch_distance: f64,
+    #[serde(default, deserialize_with = "some")]
+    pinch_distance: Option<f64>,
 
     /// Spin to achieve to trigger. Default: 60
-    #[serde(default = "default_rotation")]
-    rotation_distance: f64,
+    #[serde(default, deserialize_with = "some")]
+    rotation_distance: Option<f64>,
+
+    /// How close to the edge edge swipes start, as a fraction of the device
+    /// size. Default: 0.05
+    #[serde(default, deserialize_with = "some")]
+    edge_margin: Option<f64>,
+
+    /// Longest time in ms fingers can stay down for a tap. Default: 200
+    #[serde(default, deserialize_with = "some")]
+    tap_time: Option<u32>,
+
+    /// Longest time in ms between taps to count them as multi-tap. Default: 300
+    #[serde(default, deserialize_with = "some")]
+    tap_interval: Option<u32>,
+
+    /// Mouse button to hold while drawing mouse gestures. Default: none, mouse
+    /// gestures are disabled
+    #[serde(default, deserialize_with = "some")]
+    mouse_gesture_button: Option<MouseButton>,
+
+    /// Pause in ms after each key step of key macros, unless the macro sets
+    /// its own. Default: 10
+    #[serde(default, deserialize_with = "some")]
+    key_delay: Option<u64>,
+
+    /// How similar a drawing must be to a shape, from 0 to 1. Default: 0.8
+    #[serde(default, deserialize_with = "some")]
+    shape_threshold: Option<f64>,
+
+    /// Shapes to recognize in drawings, several per name are fine
+    #[serde(default)]
+    shapes: Vec<ConfigShape>,
 
     /// Triggers executed with any display manager and any window
     #[serde(default = "default_triggers")]
@@ -36,101 +88,650 @@ pub struct Config {
     /// Triggers executed in wayland on any window
     #[serde(default = "default_triggers")]
     wayland_triggers: Vec<ConfigTrigger>,
+
+    /// Triggers executed only when their condition holds, like in a certain
+    /// desktop environment
+    #[serde(default)]
+    conditional_triggers: Vec<ConditionalTriggers>,
+
+    /// Named trigger sets, active when switched to with SwitchLayerAction
+    #[serde(default)]
+    layers: BTreeMap<String, Vec<ConfigTrigger>>,
 }
 
 #[derive(PartialEq, Debug, Deserialize)]
 pub struct ConfigTrigger {
+    /// Triggers with the same name in later files replace earlier ones
+    #[serde(default, deserialize_with = "some")]
+    pub name: Option<String>,
     pub trigger: Trigger,
+    /// Keyboard modifiers to hold during the gesture. Default: none
+    #[serde(default)]
+    pub modifiers: Vec<key::ConfigKey>,
     pub action: ConfigAction,
 }
 
+#[derive(PartialEq, Debug, Deserialize)]
+pub struct ConditionalTriggers {
+    pub when: Condition,
+    pub triggers: Vec<ConfigTrigger>,
+}
+
+#[derive(PartialEq, Debug, Deserialize)]
+pub struct ConfigShape {
+    pub name: String,
+    pub points: Vec<(f64, f64)>,
+}
+
+#[allow(clippy::enum_variant_names)]
 #[derive(PartialEq, Debug, Deserialize)]
 pub enum ConfigAction {
     UinputAction {
+        #[serde(default)]
         modifiers: Vec<key::ConfigKey>,
+        #[serde(default)]
         sequence: Vec<key::ConfigKey>,
+        /// Chords like "Ctrl+Shift+T", instead of modifiers and sequence
+        #[serde(default, deserialize_with = "some")]
+        keys: Option<key::KeyChords>,
     },
     CommandAction {
         path: String,
         args: Vec<String>,
+        #[serde(default)]
+        mode: CommandMode,
+        /// Milliseconds before the command is stopped. Default: no limit
+        #[serde(default, deserialize_with = "some")]
+        timeout: Option<u64>,
+        #[serde(default)]
+        kill_on_gesture_end: bool,
     },
     ShellCommandAction {
         command: String,
+        #[serde(default)]
+        mode: CommandMode,
+        #[serde(default, deserialize_with = "some")]
+        timeout: Option<u64>,
+        #[serde(default)]
+        kill_on_gesture_end: bool,
+    },
+    /// Run a sway or i3 command, like "workspace next"
+    SwayAction {
+        command: String,
+    },
+    /// Write a line to a helper command that keeps running
+    PipeAction {
+        command: String,
+        /// Placeholders like {fingers} are replaced. Default: "{trigger}"
+        #[serde(default = "default_pipe_line")]
+        line: String,
+    },
+    TypeTextAction {
+        text: String,
+    },
+    KeyMacroAction {
+        steps: Vec<ConfigKeyStep>,
+        /// Pause in ms after each key step. Default: key_delay
+        #[serde(default, deserialize_with = "some")]
+        delay: Option<u64>,
+    },
+    SwitchLayerAction {
+        layer: String,
+        mode: LayerMode,
+    },
+    MouseClickAction {
+        button: MouseButton,
+    },
+    /// Actions run in order, stopping at the first that fails
+    Sequence(Vec<ConfigAction>),
+    /// Actions all run, even when some fail. They are started one after
+    /// another, so blocking ones hold up the rest
+    Parallel(Vec<ConfigAction>),
+    /// Condition, the action when it holds, and the action when it doesn't
+    If(ActionCondition, Box<ConfigAction>, Box<ConfigAction>),
+    MediaAction {
+        command: MediaCommand,
+        /// Like spotify or vlc. Default: the one playing
+        #[serde(default, deserialize_with = "some")]
+        player: Option<String>,
+    },
+    DBusAction {
+        /// Default: Session
+        #[serde(default)]
+        bus: Bus,
+        destination: String,
+        path: String,
+        interface: String,
+        method: String,
+        #[serde(default)]
+        args: Vec<DBusValue>,
+    },
+    /// In wheel detents, fractions are fine
+    ScrollAction {
+        #[serde(default)]
+        vertical: f64,
+        #[serde(default)]
+        horizontal: f64,
+    },
+    PointerMotionAction {
+        #[serde(default)]
+        x: i32,
+        #[serde(default)]
+        y: i32,
     },
 }
 
+#[derive(PartialEq, Debug, Deserialize)]
+pub enum ConfigKeyStep {
+    Press(key::ConfigKey),
+    Release(key::ConfigKey),
+    Click(key::ConfigKey),
+    /// Pause in ms
+    Delay(u64),
+    Repeat(u32, Vec<ConfigKeyStep>),
+}
+
+impl ConfigKeyStep {
+    fn make(self) -> action::KeyStep {
+        match self {
+            ConfigKeyStep::Press(key) => action::KeyStep::Press(key.0),
+            ConfigKeyStep::Release(key) => action::KeyStep::Release(key.0),
+            ConfigKeyStep::Click(key) => action::KeyStep::Click(key.0),
+            ConfigKeyStep::Delay(ms) => action::KeyStep::Delay(Duration::from_millis(ms)),
+            ConfigKeyStep::Repeat(times, steps) => {
+                action::KeyStep::Repeat(times, steps.into_iter().map(Self::make).collect())
+            }
+        }
+    }
+}
+
+/// What actions are made with: devices and state they share, and defaults
+/// from the config
+pub struct ActionEnv {
+    pub input_device: Rc<RefCell<uinput::Device>>,
+    /// Created when the first mouse action needs it
+    pointer: RefCell<Option<Rc<RefCell<uinput::Device>>>>,
+    pub layers: Layers,
+    pub buses: action::Buses,
+    pub last_player: action::LastPlayer,
+    pub gestures: GestureCount,
+    pub pipes: action::Pipes,
+    pub key_delay: u64,
+}
+
+impl ActionEnv {
+    fn lifecycle(
+        &self,
+        mode: CommandMode,
+        timeout: Option<u64>,
+        kill_on_gesture_end: bool,
+    ) -> action::Lifecycle {
+        action::Lifecycle::new(
+            mode,
+            timeout.map(Duration::from_millis),
+            kill_on_gesture_end,
+            self.gestures.clone(),
+        )
+    }
+
+    fn pointer(&self) -> Result<Rc<RefCell<uinput::Device>>, Error> {
+        let mut pointer = self.pointer.borrow_mut();
+        match *pointer {
+            Some(ref device) => Ok(device.clone()),
+            None => {
+                let device = action::pointer_device()?;
+                *pointer = Some(device.clone());
+                Ok(device)
+            }
+        }
+    }
+}
+
 impl ConfigAction {
-    pub fn make(
-        self,
-        input_device: &std::rc::Rc<std::cell::RefCell<uinput::Device>>,
-    ) -> Box<dyn action::Action> {
+    /// This action and the ones inside it
+    pub(super) fn nested(&self) -> Vec<&ConfigAction> {
+        let mut all = vec![self];
         match self {
+            ConfigAction::Sequence(actions) | ConfigAction::Parallel(actions) => {
+                all.extend(actions.iter().flat_map(ConfigAction::nested))
+            }
+            ConfigAction::If(_, then, otherwise) => {
+                all.extend(then.nested());
+                all.extend(otherwise.nested());
+            }
+            _ => (),
+        }
+        all
+    }
+
+    pub fn make(self, env: &ActionEnv) -> Result<Box<dyn action::Action>, Error> {
+        Ok(match self {
             ConfigAction::UinputAction {
                 modifiers,
                 sequence,
-            } => Box::new(action::UinputAction {
-                device: input_device.clone(),
-                modifiers: modifiers.iter().map(|x| x.0).collect(),
-                sequence: sequence.iter().map(|x| x.0).collect(),
-            }),
-            ConfigAction::CommandAction { path, args } => {
-                Box::new(action::CommandAction { path, args })
+                keys,
+            } => {
+                let chords = match keys {
+                    Some(keys) => keys
+                        .0
+                        .into_iter()
+                        .map(|c| action::KeyChord {
+                            modifiers: c.modifiers.iter().map(|x| x.0).collect(),
+                            sequence: vec![c.key.0],
+                        })
+                        .collect(),
+                    None => vec![action::KeyChord {
+                        modifiers: modifiers.iter().map(|x| x.0).collect(),
+                        sequence: sequence.iter().map(|x| x.0).collect(),
+                    }],
+                };
+                Box::new(action::UinputAction {
+                    device: env.input_device.clone(),
+                    chords,
+                })
             }
-            ConfigAction::ShellCommandAction { command } => {
-                Box::new(action::ShellCommandAction { command })
+            ConfigAction::SwayAction { command } => Box::new(action::SwayAction { command }),
+            ConfigAction::PipeAction { command, line } => Box::new(action::PipeAction {
+                pipes: env.pipes.clone(),
+                command,
+                line,
+            }),
+            ConfigAction::TypeTextAction { text } => Box::new(action::UinputAction {
+                device: env.input_device.clone(),
+                // the config check reports text that can't be typed
+                chords: keymap::text_chords(&text).unwrap_or_default(),
+            }),
+            ConfigAction::KeyMacroAction { steps, delay } => Box::new(action::KeyMacroAction {
+                device: env.input_device.clone(),
+                steps: steps.into_iter().map(ConfigKeyStep::make).collect(),
+                delay: Duration::from_millis(delay.unwrap_or(env.key_delay)),
+            }),
+            ConfigAction::CommandAction {
+                path,
+                args,
+                mode,
+                timeout,
+                kill_on_gesture_end,
+            } => Box::new(action::CommandAction {
+                path,
+                args,
+                lifecycle: env.lifecycle(mode, timeout, kill_on_gesture_end),
+            }),
+            ConfigAction::ShellCommandAction {
+                command,
+                mode,
+                timeout,
+                kill_on_gesture_end,
+            } => Box::new(action::ShellCommandAction {
+                command,
+                lifecycle: env.lifecycle(mode, timeout, kill_on_gesture_end),
+            }),
+            ConfigAction::SwitchLayerAction { layer, mode } => {
+                Box::new(action::SwitchLayerAction {
+                    layers: env.layers.clone(),
+                    layer,
+                    mode,
+                })
             }
-        }
+            ConfigAction::DBusAction {
+                bus,
+                destination,
+                path,
+                interface,
+                method,
+                args,
+            } => Box::new(action::DBusAction {
+                buses: env.buses.clone(),
+                bus,
+                destination,
+                path,
+                interface,
+                method,
+                args,
+            }),
+            ConfigAction::Sequence(actions) => Box::new(action::SequenceAction {
+                actions: make_all(actions, env)?,
+            }),
+            ConfigAction::Parallel(actions) => Box::new(action::ParallelAction {
+                actions: make_all(actions, env)?,
+            }),
+            ConfigAction::If(condition, then, otherwise) => Box::new(action::IfAction {
+                condition,
+                then: then.make(env)?,
+                otherwise: otherwise.make(env)?,
+            }),
+            ConfigAction::MediaAction { command, player } => Box::new(action::MediaAction {
+                buses: env.buses.clone(),
+                last: env.last_player.clone(),
+                command,
+                player,
+            }),
+            ConfigAction::MouseClickAction { button } => Box::new(action::MouseClickAction {
+                device: env.pointer()?,
+                button,
+            }),
+            ConfigAction::ScrollAction {
+                vertical,
+                horizontal,
+            } => Box::new(action::ScrollAction {
+                device: env.pointer()?,
+                vertical,
+                horizontal,
+                remainder: (0, 0),
+            }),
+            ConfigAction::PointerMotionAction { x, y } => Box::new(action::PointerMotionAction {
+                device: env.pointer()?,
+                x,
+                y,
+            }),
+        })
     }
 }
 
+fn make_all(
+    actions: Vec<ConfigAction>,
+    env: &ActionEnv,
+) -> Result<Vec<Box<dyn action::Action>>, Error> {
+    actions.into_iter().map(|a| a.make(env)).collect()
+}
+
+/// Triggers to observe and actions to execute on them, matched by index
+pub struct Bindings {
+    pub triggers: Vec<gesture::Binding>,
+    pub actions: Vec<Box<dyn action::Action>>,
+    /// Layer state shared by the adapter and layer actions
+    pub layers: Layers,
+    /// Shared by the adapter and command actions
+    pub gestures: GestureCount,
+}
+
 /* Impls */
 
 impl Config {
-    pub fn load<P>(path: P) -> std::io::Result<Config>
-    where
-        P: AsRef<std::path::Path> + std::fmt::Display,
-    {
-        log::trace!("Reading {}", path);
-        let s = std::fs::read_to_string(path).map_err(|e| {
-            log::error!("Error reading config: {}", e);
-            e
+    pub fn mouse_gesture_button(&self) -> Option<MouseButton> {
+        self.mouse_gesture_button
+    }
+
+    /// Load the files in order, each one adding to and overriding the ones
+    /// before. Files that don't exist are skipped, but at least one must
+    pub fn load_layered(paths: &[String]) -> Result<Config, Error> {
+        let mut config = None::<Config>;
+        for path in paths.iter().filter(|p| Path::new(p).exists()) {
+            let layer = Config::load_included(Path::new(path), &mut Vec::new())?;
+            match config {
+                Some(ref mut c) => c.merge(layer),
+                None => config = Some(layer),
+            }
+        }
+        match config {
+            Some(c) => c.checked(),
+            // Report the file the user most likely wanted
+            None => Config::load(paths.last().map_or("", |p| p.as_str())),
+        }
+    }
+
+    /// Load the config with all its includes, and check it for mistakes
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
+        Config::load_included(path.as_ref(), &mut Vec::new())?.checked()
+    }
+
+    fn checked(self) -> Result<Config, Error> {
+        self.check()?;
+        for warning in self.warnings() {
+            log::warn!("{}", warning);
+        }
+        Ok(self)
+    }
+
+    fn load_included(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Config, Error> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        if stack.contains(&canonical) {
+            return Err(Error::IncludeCycle(path.display().to_string()));
+        }
+        let mut config = Config::load_file(path)?;
+        config.check_names()?;
+        stack.push(canonical);
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut merged = Config::default();
+        for include in std::mem::take(&mut config.include) {
+            log::debug!("{} includes {}", path.display(), include);
+            merged.merge(Config::load_included(&dir.join(include), stack)?);
+        }
+        stack.pop();
+        merged.merge(config);
+        Ok(merged)
+    }
+
+    fn load_file(path: &Path) -> Result<Config, Error> {
+        log::trace!("Reading {}", path.display());
+        let s = std::fs::read_to_string(path).map_err(|source| {
+            log::error!("Error reading config: {}", source);
+            Error::ConfigRead {
+                path: path.display().to_string(),
+                source,
+            }
         })?;
         ron::from_str(&s).map_err(|e| {
             log::error!("Error decoding RON: {}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, e)
+            let ron::error::Position { line, col } = e.position;
+            Error::ConfigParse {
+                path: path.display().to_string(),
+                line,
+                column: col,
+                message: e.code.to_string(),
+                snippet: snippet(&s, line, col),
+            }
         })
     }
 
-    pub fn make_triggers(
-        self,
-        is_wayland: bool,
-    ) -> (Vec<gesture::Trigger>, Vec<Box<dyn action::Action>>) {
-        let input_device = action::UinputAction::default_device();
-        self.global_triggers
+    pub fn make_triggers(mut self, is_wayland: bool) -> Result<Bindings, Error> {
+        let shapes = Rc::new(self.make_shapes()?);
+        let action_env = ActionEnv {
+            input_device: action::UinputAction::default_device()?,
+            pointer: RefCell::new(None),
+            buses: action::Buses::default(),
+            last_player: action::LastPlayer::default(),
+            gestures: GestureCount::default(),
+            pipes: action::Pipes::default(),
+            layers: Layers::default(),
+            key_delay: self.key_delay(),
+        };
+        let global = std::mem::take(&mut self.global_triggers);
+        let local = std::mem::take(if is_wayland {
+            &mut self.wayland_triggers
+        } else {
+            &mut self.x11_triggers
+        });
+        let local_section = if is_wayland {
+            "wayland_triggers"
+        } else {
+            "x11_triggers"
+        };
+        let env = |name: &str| std::env::var(name).ok();
+        let conditional = std::mem::take(&mut self.conditional_triggers)
             .into_iter()
-            .chain(
-                if is_wayland {
-                    self.wayland_triggers
-                } else {
-                    self.x11_triggers
+            .enumerate()
+            .filter(|(_, c)| {
+                let holds = c.when.holds(&env, is_wayland);
+                log::debug!("Condition {:?} holds: {}", c.when, holds);
+                holds
+            })
+            .map(|(i, c)| (format!("conditional_triggers[{}]", i), None, c.triggers));
+        let layered = std::mem::take(&mut self.layers);
+        let layer_names = layered.keys().cloned().collect::<Vec<_>>();
+        // Section of the config, layer, and the triggers there
+        let sections = [
+            ("global_triggers".to_owned(), None, global),
+            (local_section.to_owned(), None, local),
+        ]
+        .into_iter()
+        .chain(conditional)
+        .chain(
+            layered
+                .into_iter()
+                .map(|(name, triggers)| (format!("layers[{}]", name), Some(name), triggers)),
+        );
+
+        let mut triggers = Vec::<gesture::Binding>::new();
+        let mut actions = Vec::new();
+        for (section, layer, entries) in sections {
+            for (i, x) in entries.into_iter().enumerate() {
+                let name = check::label(&section, i, &x);
+                if x.name.is_some() && triggers.iter().any(|t| t.name == name) {
+                    return Err(Error::InvalidTrigger(format!(
+                        "duplicate trigger name {}",
+                        name
+                    )));
                 }
-                .into_iter(),
-            )
-            .map(|x| {
-                (
-                    x.trigger.make(
-                        self.swipe_distance,
-                        self.shear_distance,
-                        self.pinch_distance,
-                        self.rotation_distance,
-                    ),
-                    x.action.make(&input_device),
-                )
+                for action in x.action.nested() {
+                    if let ConfigAction::SwitchLayerAction { ref layer, .. } = action {
+                        if !layer_names.contains(layer) {
+                            return Err(Error::InvalidAction(format!("unknown layer: {}", layer))
+                                .in_trigger(&name));
+                        }
+                    }
+                }
+                triggers.push(gesture::Binding {
+                    trigger: x
+                        .trigger
+                        .make(&self, &shapes)
+                        .map_err(|e| e.in_trigger(&name))?,
+                    modifiers: key::make_modifiers(&x.modifiers)
+                        .map_err(|e| e.in_trigger(&name))?,
+                    layer: layer.clone(),
+                    name,
+                });
+                actions.push(x.action.make(&action_env)?);
+            }
+        }
+        Ok(Bindings {
+            triggers,
+            actions,
+            layers: action_env.layers,
+            gestures: action_env.gestures,
+        })
+    }
+}
+
+impl Config {
+    /// Add triggers from the other config and take the options it sets
+    fn merge(&mut self, other: Config) {
+        macro_rules! take_set {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() {
+                    self.$field = other.$field;
+                })*
+            };
+        }
+        take_set!(
+            swipe_distance,
+            shear_distance,
+            pinch_distance,
+            rotation_distance,
+            edge_margin,
+            tap_time,
+            tap_interval,
+            mouse_gesture_button,
+            key_delay,
+            shape_threshold
+        );
+        self.shapes.extend(other.shapes);
+        merge_triggers(&mut self.global_triggers, other.global_triggers);
+        merge_triggers(&mut self.x11_triggers, other.x11_triggers);
+        merge_triggers(&mut self.wayland_triggers, other.wayland_triggers);
+        self.conditional_triggers.extend(other.conditional_triggers);
+        for (name, triggers) in other.layers {
+            merge_triggers(self.layers.entry(name).or_default(), triggers);
+        }
+    }
+
+    fn swipe_distance(&self) -> u32 {
+        self.swipe_distance.unwrap_or_else(default_distance)
+    }
+
+    fn shear_distance(&self) -> u32 {
+        self.shear_distance.unwrap_or_else(default_distance)
+    }
+
+    fn pinch_distance(&self) -> f64 {
+        self.pinch_distance.unwrap_or_else(default_pinch)
+    }
+
+    fn rotation_distance(&self) -> f64 {
+        self.rotation_distance.unwrap_or_else(default_rotation)
+    }
+
+    fn edge_margin(&self) -> f64 {
+        self.edge_margin.unwrap_or_else(default_edge_margin)
+    }
+
+    fn tap_time(&self) -> u32 {
+        self.tap_time.unwrap_or_else(default_tap_time)
+    }
+
+    fn tap_interval(&self) -> u32 {
+        self.tap_interval.unwrap_or_else(default_tap_interval)
+    }
+
+    fn key_delay(&self) -> u64 {
+        self.key_delay.unwrap_or_else(default_key_delay)
+    }
+
+    fn shape_threshold(&self) -> f64 {
+        self.shape_threshold.unwrap_or_else(default_shape_threshold)
+    }
+
+    fn make_shapes(&self) -> Result<Shapes, Error> {
+        let templates = self
+            .shapes
+            .iter()
+            .map(|s| {
+                Template::new(s.name.clone(), &s.points)
+                    .ok_or_else(|| Error::InvalidTrigger(format!("shape {} has no size", s.name)))
             })
-            .unzip()
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Shapes::new(templates, self.shape_threshold()))
     }
 }
 
+/// Named triggers replace the ones with the same name, the rest are added
+fn merge_triggers(triggers: &mut Vec<ConfigTrigger>, other: Vec<ConfigTrigger>) {
+    for trigger in other {
+        let existing = trigger
+            .name
+            .as_ref()
+            .and_then(|name| triggers.iter_mut().find(|t| t.name.as_ref() == Some(name)));
+        match existing {
+            Some(t) => {
+                log::debug!("Overriding trigger {:?}", t.name);
+                *t = trigger;
+            }
+            None => triggers.push(trigger),
+        }
+    }
+}
+
+/// The line of the config with a caret under the column, both counted from 1
+fn snippet(text: &str, line: usize, column: usize) -> String {
+    let code = text.lines().nth(line.saturating_sub(1)).unwrap_or("");
+    // keep tabs so that the caret lines up
+    let indent = code
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    format!("{:>5} | {}\n      | {}^", line, code, indent)
+}
+
+/// Optional fields that are written without Some in the config
+fn some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
 /* Serde defaults */
 
 fn default_distance() -> u32 {
@@ -145,7 +746,153 @@ fn default_rotation() -> f64 {
     log::debug!("Using default rotation");
     60.0
 }
+fn default_edge_margin() -> f64 {
+    log::debug!("Using default edge margin");
+    0.05
+}
+fn default_tap_time() -> u32 {
+    log::debug!("Using default tap time");
+    200
+}
+fn default_tap_interval() -> u32 {
+    log::debug!("Using default tap interval");
+    300
+}
+fn default_pipe_line() -> String {
+    "{trigger}".to_owned()
+}
+fn default_key_delay() -> u64 {
+    log::debug!("Using default key delay");
+    10
+}
+fn default_shape_threshold() -> f64 {
+    log::debug!("Using default shape threshold");
+    0.8
+}
 fn default_triggers() -> Vec<ConfigTrigger> {
     log::debug!("Using default triggers");
     Vec::new()
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Config, MouseButton};
+    use crate::action::test::temp_path;
+
+    #[test]
+    fn includes() {
+        let dir = temp_path("includes");
+        std::fs::create_dir_all(&dir).unwrap();
+        let hold = |name: &str, fingers: u32, command: &str| {
+            format!(
+                r#"(name: "{}", trigger: Hold(fingers: {}), action: ShellCommandAction(command: "{}"))"#,
+                name, fingers, command
+            )
+        };
+        std::fs::write(
+            dir.join("common.ron"),
+            format!(
+                "(swipe_distance: 50, tap_time: 100, mouse_gesture_button: Right, global_triggers: [{}, {}])",
+                hold("a", 2, "common"),
+                hold("b", 3, "common")
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("config.ron"),
+            format!(
+                r#"(include: ["common.ron"], swipe_distance: 70, global_triggers: [{}, {}])"#,
+                hold("b", 3, "mine"),
+                hold("c", 4, "mine")
+            ),
+        )
+        .unwrap();
+        std::fs::write(dir.join("loop.ron"), r#"(include: ["loop.ron"])"#).unwrap();
+
+        let config = Config::load(dir.join("config.ron")).unwrap();
+        assert_eq!(config.swipe_distance(), 70);
+        assert_eq!(config.tap_time(), 100);
+        assert_eq!(config.mouse_gesture_button(), Some(MouseButton::Right));
+        let triggers = config
+            .global_triggers
+            .iter()
+            .map(|t| (t.name.as_deref().unwrap(), format!("{:?}", t.action)))
+            .collect::<Vec<_>>();
+        assert_eq!(triggers.len(), 3);
+        assert_eq!(triggers[0].0, "a");
+        assert!(triggers[1].1.contains("mine"));
+        assert_eq!(triggers[2].0, "c");
+        assert!(Config::load(dir.join("loop.ron")).is_err());
+
+        // the user config extends the system one, and missing files are skipped
+        let path = |name: &str| dir.join(name).display().to_string();
+        let config =
+            Config::load_layered(&[path("common.ron"), path("none.ron"), path("config.ron")])
+                .unwrap();
+        assert_eq!(config.swipe_distance(), 70);
+        assert_eq!(config.global_triggers.len(), 3);
+        let config = Config::load_layered(&[path("none.ron"), path("common.ron")]).unwrap();
+        assert_eq!(config.global_triggers.len(), 2);
+        assert!(Config::load_layered(&[path("none.ron")]).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn diagnostics() {
+        let path = temp_path("diagnostics");
+        std::fs::write(&path, "(\n    swipe_distance: 100,\n    tap_time: ten,\n)").unwrap();
+        let e = Config::load(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(e.contains("line 3, column 15"), "{}", e);
+        assert!(e.ends_with("    3 |     tap_time: ten,\n      |               ^"));
+
+        let config = ron::from_str::<Config>(
+            r#"(
+                edge_margin: 0.7,
+                global_triggers: [
+                    (trigger: Hold(fingers: 0), action: UinputAction(modifiers: [], sequence: [])),
+                    (trigger: Tap(fingers: 2, count: 1), action: Sequence([
+                        ShellCommandAction(command: ""),
+                        If(Env("X"), SwitchLayerAction(layer: "nope", mode: Toggle), Parallel([])),
+                    ])),
+                    (trigger: Tap(fingers: 3, count: 1), action: ShellCommandAction(command: "x", kill_on_gesture_end: true)),
+                    (trigger: Hold(fingers: 4), action: KeyMacroAction(steps: [Repeat(200, [Click("A")])])),
+                    (name: "hold", trigger: Hold(fingers: 5), action: ShellCommandAction(command: "x")),
+                ],
+                x11_triggers: [
+                    (name: "tap", trigger: Tap(fingers: 2, count: 1), action: ShellCommandAction(command: "")),
+                    (name: "hold", trigger: Hold(fingers: 6), action: ShellCommandAction(command: "x")),
+                ],
+                wayland_triggers: [
+                    (name: "tap", trigger: Tap(fingers: 2, count: 1), action: ShellCommandAction(command: "")),
+                ],
+            )"#,
+        )
+        .unwrap();
+        let problems = match config.check() {
+            Err(crate::error::Error::ConfigCheck(problems)) => problems,
+            r => panic!("{:?}", r),
+        };
+        assert_eq!(
+            problems,
+            vec![
+                "edge_margin must be between 0 and 0.5",
+                "global_triggers[0]: fingers must be at least 1",
+                "global_triggers[1]: unknown layer: nope",
+                "global_triggers[3]: KeyMacroAction takes 2000 ms, more than 1000 ms, and \
+                 gestures aren't recognized meanwhile",
+                "trigger name hold is used in both global_triggers and x11_triggers",
+            ]
+        );
+        assert_eq!(
+            config.warnings(),
+            vec![
+                "global_triggers[0]: UinputAction presses no keys",
+                "global_triggers[2]: kill_on_gesture_end stops the command when the next \
+                 gesture ends, as this trigger fires when its gesture ends",
+                "tap: same gesture as global_triggers[1]",
+            ]
+        );
+    }
+}
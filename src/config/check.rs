@@ -0,0 +1,376 @@
+//! Mistakes that parse fine but can't work, like gestures with no fingers, and
+//! likely ones that still run, like two triggers for the same gesture. All of
+//! them are reported at once, so that they can be fixed in one go
+
+use super::trigger::Trigger;
+use super::{key, keymap, Config, ConfigAction, ConfigKeyStep, ConfigTrigger};
+use crate::action;
+use crate::common::CommandMode;
+use crate::error::Error;
+
+/// Longest a key macro can take in ms, as gestures aren't recognized while it
+/// runs
+const MAX_MACRO_TIME: u64 = 1000;
+
+/// Most key presses and releases a macro can have
+const MAX_MACRO_STEPS: u64 = 1000;
+
+/// Problems that stop the config from loading, and ones that are only warned
+/// about
+#[derive(Default)]
+struct Findings {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl Config {
+    /// Lists of triggers with the place in the config they come from
+    pub(super) fn sections(&self) -> Vec<(String, &Vec<ConfigTrigger>)> {
+        let mut sections = vec![
+            ("global_triggers".to_owned(), &self.global_triggers),
+            ("x11_triggers".to_owned(), &self.x11_triggers),
+            ("wayland_triggers".to_owned(), &self.wayland_triggers),
+        ];
+        for (i, c) in self.conditional_triggers.iter().enumerate() {
+            sections.push((format!("conditional_triggers[{}]", i), &c.triggers));
+        }
+        for (name, triggers) in &self.layers {
+            sections.push((format!("layers[{}]", name), triggers));
+        }
+        sections
+    }
+
+    /// Names must be unique in each list of triggers of a file, as they're
+    /// used to override triggers from other files
+    pub(super) fn check_names(&self) -> Result<(), Error> {
+        for (_, list) in self.sections() {
+            let mut names = list
+                .iter()
+                .filter_map(|t| t.name.as_ref())
+                .collect::<Vec<_>>();
+            names.sort();
+            if let Some(w) = names.windows(2).find(|w| w[0] == w[1]) {
+                return Err(Error::InvalidTrigger(format!(
+                    "duplicate trigger name {}",
+                    w[0]
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Look for everything that would make the config not work as written
+    pub fn check(&self) -> Result<(), Error> {
+        let problems = self.findings().errors;
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::ConfigCheck(problems))
+        }
+    }
+
+    /// Likely mistakes that don't stop the config from working, like triggers
+    /// that do nothing or that fire on the same gesture as another one
+    pub fn warnings(&self) -> Vec<String> {
+        self.findings().warnings
+    }
+
+    fn findings(&self) -> Findings {
+        let mut findings = Findings {
+            errors: self.check_options(),
+            warnings: Vec::new(),
+        };
+        let sections = self.sections();
+        for (section, triggers) in &sections {
+            for (i, t) in triggers.iter().enumerate() {
+                let name = label(section, i, t);
+                let found = self.check_trigger(t);
+                for problem in found.errors {
+                    findings.errors.push(format!("{}: {}", name, problem));
+                }
+                for warning in found.warnings {
+                    findings.warnings.push(format!("{}: {}", name, warning));
+                }
+            }
+        }
+
+        // Names are unique among triggers that can be active at the same
+        // time, which is all of them except X11 and Wayland ones
+        let named = sections
+            .iter()
+            .flat_map(|(s, ts)| ts.iter().filter_map(move |t| Some((s, t.name.as_ref()?))))
+            .collect::<Vec<_>>();
+        for (i, (section, name)) in named.iter().enumerate() {
+            let exclusive = |other: &str| {
+                matches!(
+                    (section.as_str(), other),
+                    ("x11_triggers", "wayland_triggers")
+                )
+            };
+            if let Some((other, _)) = named[i + 1..]
+                .iter()
+                .find(|(o, n)| n == name && o != section && !exclusive(o))
+            {
+                findings.errors.push(format!(
+                    "trigger name {} is used in both {} and {}",
+                    name, section, other
+                ));
+            }
+        }
+
+        // Triggers that are active at the same time
+        let global = labeled(&sections[0].0, sections[0].1);
+        for (i, (section, triggers)) in sections.iter().enumerate() {
+            let mut scope = labeled(section, triggers);
+            // global triggers are active along with the local ones
+            if i == 1 || i == 2 {
+                scope = global.iter().cloned().chain(scope).collect();
+            }
+            for warning in duplicates(&scope) {
+                if !findings.warnings.contains(&warning) {
+                    findings.warnings.push(warning);
+                }
+            }
+        }
+        findings
+    }
+
+    fn check_options(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut require = |ok: bool, problem: &str| {
+            if !ok {
+                problems.push(problem.to_owned());
+            }
+        };
+        require(
+            self.swipe_distance != Some(0),
+            "swipe_distance must be above 0",
+        );
+        require(
+            self.shear_distance != Some(0),
+            "shear_distance must be above 0",
+        );
+        require(
+            self.pinch_distance.is_none_or(|d| d > 1.0),
+            "pinch_distance is a scale and must be above 1",
+        );
+        require(
+            self.rotation_distance.is_none_or(|d| d > 0.0 && d < 360.0),
+            "rotation_distance must be between 0 and 360 degrees",
+        );
+        require(
+            self.edge_margin.is_none_or(|m| m > 0.0 && m < 0.5),
+            "edge_margin must be between 0 and 0.5",
+        );
+        require(self.tap_time != Some(0), "tap_time must be above 0");
+        require(self.tap_interval != Some(0), "tap_interval must be above 0");
+        require(
+            self.shape_threshold
+                .is_none_or(|t| (0.0..=1.0).contains(&t)),
+            "shape_threshold must be between 0 and 1",
+        );
+        problems
+    }
+
+    fn check_trigger(&self, t: &ConfigTrigger) -> Findings {
+        let mut findings = Findings::default();
+        let problems = &mut findings.errors;
+        match t.trigger {
+            Trigger::Swipe { fingers, .. }
+            | Trigger::EdgeSwipe { fingers, .. }
+            | Trigger::Shear { fingers, .. }
+            | Trigger::Pinch { fingers, .. }
+            | Trigger::Rotate { fingers, .. }
+            | Trigger::Hold { fingers }
+            | Trigger::Tap { fingers, .. }
+            | Trigger::Shape { fingers, .. }
+                if fingers == 0 =>
+            {
+                problems.push("fingers must be at least 1".to_owned())
+            }
+            _ => (),
+        }
+        match t.trigger {
+            Trigger::Tap { count: 0, .. } => problems.push("count must be at least 1".to_owned()),
+            Trigger::MouseGesture { ref strokes } if strokes.is_empty() => {
+                problems.push("strokes must not be empty".to_owned())
+            }
+            Trigger::Shape { ref name, .. } if !self.shapes.iter().any(|s| s.name == *name) => {
+                problems.push(format!("unknown shape: {}", name))
+            }
+            _ => (),
+        }
+        if let Err(e) = key::make_modifiers(&t.modifiers) {
+            problems.push(e.to_string());
+        }
+        for action in t.action.nested() {
+            self.check_action(action, &mut findings);
+        }
+        let fires_at_end = matches!(
+            t.trigger,
+            Trigger::Hold { .. }
+                | Trigger::Tap { .. }
+                | Trigger::MouseGesture { .. }
+                | Trigger::Shape { .. }
+        );
+        let kills = t.action.nested().into_iter().any(|a| {
+            matches!(
+                a,
+                ConfigAction::CommandAction {
+                    kill_on_gesture_end: true,
+                    ..
+                } | ConfigAction::ShellCommandAction {
+                    kill_on_gesture_end: true,
+                    ..
+                }
+            )
+        });
+        if fires_at_end && kills {
+            findings.warnings.push(
+                "kill_on_gesture_end stops the command when the next gesture ends, as this \
+                 trigger fires when its gesture ends"
+                    .to_owned(),
+            );
+        }
+        findings
+    }
+
+    fn check_action(&self, action: &ConfigAction, findings: &mut Findings) {
+        let problems = &mut findings.errors;
+        match *action {
+            ConfigAction::UinputAction {
+                ref modifiers,
+                ref sequence,
+                keys: Some(_),
+            } if !modifiers.is_empty() || !sequence.is_empty() => {
+                problems.push("UinputAction takes either keys or modifiers and sequence".to_owned())
+            }
+            ConfigAction::UinputAction {
+                ref modifiers,
+                ref sequence,
+                ref keys,
+            } if modifiers.is_empty()
+                && sequence.is_empty()
+                && keys.as_ref().is_none_or(|k| k.0.is_empty()) =>
+            {
+                findings
+                    .warnings
+                    .push("UinputAction presses no keys".to_owned())
+            }
+            ConfigAction::CommandAction {
+                mode,
+                timeout,
+                kill_on_gesture_end,
+                ..
+            }
+            | ConfigAction::ShellCommandAction {
+                mode,
+                timeout,
+                kill_on_gesture_end,
+                ..
+            } => {
+                if timeout == Some(0) {
+                    problems.push("timeout must be above 0".to_owned())
+                }
+                if kill_on_gesture_end && mode == CommandMode::Wait {
+                    problems.push(
+                        "kill_on_gesture_end can't work with mode Wait, as the gesture can't end \
+                         while wzmach waits"
+                            .to_owned(),
+                    )
+                }
+            }
+            ConfigAction::KeyMacroAction { ref steps, .. } if steps.is_empty() => findings
+                .warnings
+                .push("KeyMacroAction has no steps".to_owned()),
+            ConfigAction::KeyMacroAction { ref steps, delay } => {
+                let (time, count) = macro_size(steps, delay.unwrap_or(self.key_delay()));
+                if time > MAX_MACRO_TIME {
+                    problems.push(format!(
+                        "KeyMacroAction takes {} ms, more than {} ms, and gestures aren't \
+                         recognized meanwhile",
+                        time, MAX_MACRO_TIME
+                    ))
+                }
+                if count > MAX_MACRO_STEPS {
+                    problems.push(format!(
+                        "KeyMacroAction has {} key steps, more than {}",
+                        count, MAX_MACRO_STEPS
+                    ))
+                }
+            }
+            ConfigAction::DBusAction {
+                ref destination,
+                ref path,
+                ref interface,
+                ref method,
+                ref args,
+                ..
+            } => {
+                if let Err(e) = action::method_call(destination, path, interface, method, args) {
+                    problems.push(format!("DBusAction {}", e))
+                }
+            }
+            ConfigAction::TypeTextAction { ref text } => {
+                if let Err(e) = keymap::text_chords(text) {
+                    problems.push(format!("TypeTextAction {}", e))
+                }
+            }
+            ConfigAction::SwitchLayerAction { ref layer, .. }
+                if !self.layers.contains_key(layer) =>
+            {
+                problems.push(format!("unknown layer: {}", layer))
+            }
+            _ => (),
+        }
+    }
+}
+
+/// How long the macro takes in ms, with the pause after each key step, and
+/// how many key steps it has with the repeats
+fn macro_size(steps: &[ConfigKeyStep], delay: u64) -> (u64, u64) {
+    steps.iter().fold((0, 0), |(time, count), step| {
+        let (t, c) = match step {
+            ConfigKeyStep::Delay(ms) => (*ms, 0),
+            ConfigKeyStep::Repeat(times, steps) => {
+                let (t, c) = macro_size(steps, delay);
+                let times = u64::from(*times);
+                (t.saturating_mul(times), c.saturating_mul(times))
+            }
+            _ => (delay, 1),
+        };
+        (time.saturating_add(t), count.saturating_add(c))
+    })
+}
+
+/// How the trigger is called in logs and errors: by name, or by its place in
+/// the config
+pub(super) fn label(section: &str, index: usize, trigger: &ConfigTrigger) -> String {
+    match trigger.name {
+        Some(ref name) => name.clone(),
+        None => format!("{}[{}]", section, index),
+    }
+}
+
+fn labeled<'a>(section: &str, triggers: &'a [ConfigTrigger]) -> Vec<(String, &'a ConfigTrigger)> {
+    triggers
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (label(section, i, t), t))
+        .collect()
+}
+
+/// Triggers that fire on the same gesture, after the first one
+fn duplicates(scope: &[(String, &ConfigTrigger)]) -> Vec<String> {
+    let modifiers = |t: &ConfigTrigger| key::make_modifiers(&t.modifiers).ok();
+    scope
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (name, t))| {
+            let (first, _) = scope[..i]
+                .iter()
+                .find(|(_, u)| u.trigger == t.trigger && modifiers(u) == modifiers(t))?;
+            Some(format!("{}: same gesture as {}", name, first))
+        })
+        .collect()
+}
@@ -0,0 +1,65 @@
+use serde::Deserialize;
+
+/// When a section of triggers applies. Desktop names are compared ignoring
+/// case, as different distributions spell them differently
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+pub enum Condition {
+    /// One of the names in XDG_CURRENT_DESKTOP, like KDE, GNOME or sway
+    Desktop(String),
+    /// XDG_SESSION_DESKTOP, like plasma, gnome or sway
+    SessionDesktop(String),
+    /// Environment variable is set
+    Env(String),
+    /// Environment variable is set to this value
+    EnvEquals(String, String),
+    Wayland,
+    X11,
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    /// Check the condition, looking up environment variables with the function
+    pub fn holds(&self, env: &dyn Fn(&str) -> Option<String>, is_wayland: bool) -> bool {
+        match self {
+            Condition::Desktop(name) => env("XDG_CURRENT_DESKTOP")
+                .is_some_and(|ds| ds.split(':').any(|d| d.eq_ignore_ascii_case(name))),
+            Condition::SessionDesktop(name) => {
+                env("XDG_SESSION_DESKTOP").is_some_and(|d| d.eq_ignore_ascii_case(name))
+            }
+            Condition::Env(var) => env(var).is_some(),
+            Condition::EnvEquals(var, value) => env(var).as_ref() == Some(value),
+            Condition::Wayland => is_wayland,
+            Condition::X11 => !is_wayland,
+            Condition::All(cs) => cs.iter().all(|c| c.holds(env, is_wayland)),
+            Condition::Any(cs) => cs.iter().any(|c| c.holds(env, is_wayland)),
+            Condition::Not(c) => !c.holds(env, is_wayland),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Condition;
+
+    #[test]
+    fn desktops() {
+        let env = |name: &str| match name {
+            "XDG_CURRENT_DESKTOP" => Some("ubuntu:GNOME".to_owned()),
+            "XDG_SESSION_DESKTOP" => Some("ubuntu".to_owned()),
+            "DOTFILES_HOST" => Some("laptop".to_owned()),
+            _ => None,
+        };
+        let holds = |s: &str| ron::from_str::<Condition>(s).unwrap().holds(&env, true);
+
+        assert!(holds(r#"Desktop("gnome")"#));
+        assert!(!holds(r#"Desktop("KDE")"#));
+        assert!(holds(r#"SessionDesktop("Ubuntu")"#));
+        assert!(holds(
+            r#"All([Wayland, EnvEquals("DOTFILES_HOST", "laptop")])"#
+        ));
+        assert!(holds(r#"Any([Desktop("sway"), Not(Env("SWAYSOCK"))])"#));
+        assert!(!holds(r#"Any([X11, Env("SWAYSOCK")])"#));
+    }
+}
@@ -1,16 +1,22 @@
+use super::keymap;
+use crate::action::is_key_code;
+use crate::error::Error;
+use crate::input_producer::event::Modifiers;
+
 use serde::Deserialize;
 use uinput::event::keyboard::{Key, KeyPad, Keyboard, Misc};
+use uinput::event::Code;
 
-/// Keyboard key with instances for deserialization
+/// Evdev code of a keyboard key, written as a name or as `Code(183)`
 #[derive(PartialEq, Debug)]
-pub struct ConfigKey(pub Keyboard);
+pub struct ConfigKey(pub u16);
 
 impl<'de> Deserialize<'de> for ConfigKey {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        deserializer.deserialize_str(KeyVisitor)
+        deserializer.deserialize_any(KeyVisitor)
     }
 }
 
@@ -19,18 +25,133 @@ impl<'de> serde::de::Visitor<'de> for KeyVisitor {
     type Value = ConfigKey;
 
     fn expecting(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(fmt, "Expecting Key string")
+        write!(fmt, "Expecting Key string or Code(number)")
     }
 
-    fn visit_str<E: serde::de::Error>(self, data: &str) -> Result<ConfigKey, E> {
-        static VARIANTS: &[&str] = &[&"KEY"];
-        match KEYS_TABLE.get(data) {
-            Some(k) => Ok(ConfigKey(*k)),
-            None => Err(E::unknown_variant(data, VARIANTS)),
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<ConfigKey, A::Error> {
+        use serde::de::Error as _;
+        let code = seq
+            .next_element::<u16>()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        if seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+            return Err(A::Error::invalid_length(2, &self));
+        }
+        if !is_key_code(code) {
+            return Err(A::Error::custom(format!(
+                "{} is not a keyboard key code",
+                code
+            )));
         }
+        Ok(ConfigKey(code))
     }
+
+    fn visit_str<E: serde::de::Error>(self, data: &str) -> Result<ConfigKey, E> {
+        find_key(data).map(ConfigKey).ok_or_else(|| {
+            E::custom(format!(
+                "unknown key {:?}, see README for the key names",
+                data
+            ))
+        })
+    }
+}
+
+/// Keys written like "Ctrl+Shift+T": the modifiers are held while the last key
+/// is clicked
+#[derive(PartialEq, Debug)]
+pub struct KeyChord {
+    pub modifiers: Vec<ConfigKey>,
+    pub key: ConfigKey,
 }
 
+/// Chords one after another, like "Ctrl+K Ctrl+C"
+#[derive(PartialEq, Debug)]
+pub struct KeyChords(pub Vec<KeyChord>);
+
+impl<'de> Deserialize<'de> for KeyChords {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_chords(&s)
+            .map(KeyChords)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn parse_chords(s: &str) -> Result<Vec<KeyChord>, String> {
+    s.split_whitespace()
+        .map(|step| {
+            let find = |name: &str| {
+                find_key(name)
+                    .map(ConfigKey)
+                    .ok_or_else(|| format!("unknown key {:?} in {:?}", name, step))
+            };
+            let mut names = step.split('+').collect::<Vec<_>>();
+            // split always gives at least one part
+            let key = find(names.pop().unwrap_or_default())?;
+            let modifiers = names.into_iter().map(find).collect::<Result<_, _>>()?;
+            Ok(KeyChord { modifiers, key })
+        })
+        .collect()
+}
+
+/// Look the key up by name ignoring case, or by one of the common short
+/// names. Other names are taken for XKB keysyms, like XF86AudioRaiseVolume,
+/// as long as they can be typed without Shift
+pub fn find_key(name: &str) -> Option<u16> {
+    let code = |k: &Keyboard| k.code() as u16;
+    if let Some(k) = KEYS_TABLE.get(name) {
+        return Some(code(k));
+    }
+    let lower = name.to_ascii_lowercase();
+    let alias = ALIASES.get(lower.as_str()).copied().unwrap_or(name);
+    let known = KEYS_TABLE
+        .entries()
+        .find(|(k, _)| k.eq_ignore_ascii_case(alias))
+        .map(|(_, v)| code(v));
+    known.or_else(|| match keymap::find_keysym(name)? {
+        (code, 0) => Some(code),
+        _ => None,
+    })
+}
+
+/// Name of the key for messages
+fn key_name(code: u16) -> String {
+    KEYS_TABLE
+        .entries()
+        .find(|(_, k)| k.code() as u16 == code)
+        .map_or_else(|| format!("Code({})", code), |(name, _)| name.to_string())
+}
+
+/// Modifiers for a trigger. Only the modifier keys can be held
+pub fn make_modifiers(keys: &[ConfigKey]) -> Result<Modifiers, Error> {
+    Modifiers::from_codes(keys.iter().map(|k| k.0 as u32)).map_err(|code| {
+        Error::InvalidTrigger(format!(
+            "{} is not a modifier, only Control, Shift, Alt and Meta keys are",
+            key_name(code as u16)
+        ))
+    })
+}
+
+/// Names people usually write, in lower case
+const ALIASES: phf::Map<&'static str, &'static str> = phf::phf_map! {
+    "ctrl" => "LeftControl",
+    "control" => "LeftControl",
+    "shift" => "LeftShift",
+    "alt" => "LeftAlt",
+    "altgr" => "RightAlt",
+    "super" => "LeftMeta",
+    "win" => "LeftMeta",
+    "meta" => "LeftMeta",
+    "escape" => "Esc",
+    "return" => "Enter",
+    "del" => "Delete",
+    "ins" => "Insert",
+    "pgup" => "PageUp",
+    "pgdn" => "PageDown",
+};
+
 // copy-pasted from uinput sources
 const KEYS_TABLE: phf::Map<&'static str, Keyboard> = phf::phf_map! {
     // classic keys
@@ -392,3 +513,36 @@ const KEYS_TABLE: phf::Map<&'static str, Keyboard> = phf::phf_map! {
     "BrighnessMin" => Keyboard::Misc(Misc::BrighnessMin),
     "BrightnessMax" => Keyboard::Misc(Misc::BrightnessMax),
 };
+
+#[cfg(test)]
+mod test {
+    use super::{parse_chords, ConfigKey, KeyChord};
+    use uinput::event::keyboard::{Key, Keyboard};
+    use uinput::event::Code;
+
+    #[test]
+    fn chords() {
+        let key = |k| ConfigKey(Keyboard::Key(k).code() as u16);
+        assert_eq!(
+            parse_chords("ctrl+Shift+t  Ctrl+K esc"),
+            Ok(vec![
+                KeyChord {
+                    modifiers: vec![key(Key::LeftControl), key(Key::LeftShift)],
+                    key: key(Key::T),
+                },
+                KeyChord {
+                    modifiers: vec![key(Key::LeftControl)],
+                    key: key(Key::K),
+                },
+                KeyChord {
+                    modifiers: vec![],
+                    key: key(Key::Esc),
+                },
+            ])
+        );
+        assert_eq!(
+            parse_chords("Super+Nope"),
+            Err(r#"unknown key "Nope" in "Super+Nope""#.to_owned())
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//! Keys that have no name in the uinput tables are looked up by their XKB
+//! keysym in the keyboard layout. The layout is set the same way as for other
+//! programs using libxkbcommon, with XKB_DEFAULT_LAYOUT and XKB_DEFAULT_VARIANT.
+//! Without them it's the layout of the X server, or the system one from
+//! localectl, and US when neither is known. Text is typed through the layout
+//! too.
+
+use crate::action::KeyChord;
+
+use xkbcommon::xkb;
+
+/// XKB key codes are evdev ones shifted by 8
+const EVDEV_OFFSET: u32 = 8;
+
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_SPACE: u16 = 57;
+const KEY_RIGHTALT: u16 = 100;
+
+thread_local! {
+    static KEYMAP: Option<xkb::Keymap> = compile(active_layout().unwrap_or_default());
+}
+
+/// Keymap of the layout and variant, leaving empty ones to libxkbcommon
+fn compile((layout, variant): (String, String)) -> Option<xkb::Keymap> {
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = xkb::Keymap::new_from_names(
+        &context,
+        "",
+        "",
+        &layout,
+        &variant,
+        None,
+        xkb::COMPILE_NO_FLAGS,
+    );
+    if keymap.is_none() {
+        log::error!("Could not compile the keyboard layout, keysyms won't work");
+    }
+    keymap
+}
+
+/// Layout and variant the user types with. None leaves them to libxkbcommon,
+/// which reads them from the environment or falls back to US
+fn active_layout() -> Option<(String, String)> {
+    if std::env::var_os("XKB_DEFAULT_LAYOUT").is_some() {
+        return None;
+    }
+    let output = |program: &str, args: &[&str]| {
+        let output = std::process::Command::new(program)
+            .args(args)
+            .stdin(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()?;
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let x11 = || {
+        std::env::var_os("DISPLAY")?;
+        parse_rules_names(&output("xprop", &["-root", "_XKB_RULES_NAMES"])?)
+    };
+    let system = || parse_localectl(&output("localectl", &["status"])?);
+    let found = x11().or_else(system);
+    match found {
+        Some((ref layout, ref variant)) => {
+            log::debug!("Keyboard layout {:?}, variant {:?}", layout, variant)
+        }
+        None => log::warn!(
+            "Could not find the keyboard layout, using US. Set XKB_DEFAULT_LAYOUT to change it"
+        ),
+    }
+    found
+}
+
+/// _XKB_RULES_NAMES(STRING) = "evdev", "pc105", "us,de", "", "grp:alt_shift_toggle"
+fn parse_rules_names(line: &str) -> Option<(String, String)> {
+    let (_, names) = line.split_once('=')?;
+    // layouts and variants can have commas of their own, so split on quotes
+    let fields = names.split('"').skip(1).step_by(2).collect::<Vec<_>>();
+    match fields.get(2) {
+        Some(layout) if !layout.is_empty() => {
+            Some((layout.to_string(), fields.get(3).unwrap_or(&"").to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Lines like "      X11 Layout: de" of localectl status
+fn parse_localectl(status: &str) -> Option<(String, String)> {
+    let field = |name: &str| {
+        status.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            (key.trim() == name).then(|| value.trim().to_owned())
+        })
+    };
+    let layout = field("X11 Layout").filter(|l| !l.is_empty() && l != "n/a")?;
+    Some((layout, field("X11 Variant").unwrap_or_default()))
+}
+
+/// Evdev code of the key with the keysym and the shift level it's on, like 0
+/// for "a" and 1 for "A"
+pub fn find_keysym(name: &str) -> Option<(u16, u32)> {
+    KEYMAP.with(|keymap| keysym_key(keymap.as_ref()?, name))
+}
+
+fn keysym_key(keymap: &xkb::Keymap, name: &str) -> Option<(u16, u32)> {
+    let keysym = xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS);
+    if keysym.raw() == xkb::keysyms::KEY_NoSymbol {
+        return None;
+    }
+    find(keymap, keysym)
+}
+
+/// Key presses that type the text. Characters that aren't on the layout are
+/// entered by their code with Ctrl+Shift+U, which GTK and IBus understand
+pub fn text_chords(text: &str) -> Result<Vec<KeyChord>, String> {
+    KEYMAP.with(|keymap| match keymap {
+        Some(keymap) => type_text(keymap, text),
+        None => Err("no keyboard layout to type text with".to_owned()),
+    })
+}
+
+fn type_text(keymap: &xkb::Keymap, text: &str) -> Result<Vec<KeyChord>, String> {
+    let char_chord = |c| char_chord(keymap, c);
+    let mut chords = Vec::new();
+    for c in text.chars() {
+        if let Some(chord) = char_chord(c) {
+            chords.push(chord);
+            continue;
+        }
+        log::debug!("{:?} is not on the keyboard layout, typing its code", c);
+        let u = char_chord('u')
+            .filter(|u| u.modifiers.is_empty())
+            .ok_or("can't type u to enter a character code")?;
+        chords.push(KeyChord {
+            modifiers: vec![KEY_LEFTCTRL, KEY_LEFTSHIFT],
+            sequence: u.sequence,
+        });
+        for digit in format!("{:x}", c as u32).chars() {
+            let chord = char_chord(digit)
+                .ok_or_else(|| format!("can't type {} to enter a character code", digit))?;
+            chords.push(chord);
+        }
+        chords.push(KeyChord {
+            modifiers: vec![],
+            sequence: vec![KEY_SPACE],
+        });
+    }
+    Ok(chords)
+}
+
+/// The key for the character, with what's needed to get to its level
+fn char_chord(keymap: &xkb::Keymap, c: char) -> Option<KeyChord> {
+    let keysym = match c {
+        '\n' => xkb::Keysym::new(xkb::keysyms::KEY_Return),
+        '\t' => xkb::Keysym::new(xkb::keysyms::KEY_Tab),
+        _ => xkb::utf32_to_keysym(c as u32),
+    };
+    let (code, level) = find(keymap, keysym)?;
+    let modifiers = match level {
+        0 => vec![],
+        1 => vec![KEY_LEFTSHIFT],
+        2 => vec![KEY_RIGHTALT],
+        3 => vec![KEY_RIGHTALT, KEY_LEFTSHIFT],
+        _ => return None,
+    };
+    Some(KeyChord {
+        modifiers,
+        sequence: vec![code],
+    })
+}
+
+/// Find the key with the lowest level that gives the keysym
+fn find(keymap: &xkb::Keymap, keysym: xkb::Keysym) -> Option<(u16, u32)> {
+    let mut found = None::<(u16, u32)>;
+    for raw in keymap.min_keycode().raw()..=keymap.max_keycode().raw() {
+        let keycode = xkb::Keycode::new(raw);
+        for level in 0..keymap.num_levels_for_key(keycode, 0) {
+            let better = found.is_none_or(|(_, l)| level < l);
+            if better && keymap.key_get_syms_by_level(keycode, 0, level) == [keysym] {
+                found = Some(((raw - EVDEV_OFFSET) as u16, level));
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compile, keysym_key, parse_localectl, parse_rules_names, type_text};
+    use crate::action::KeyChord;
+
+    use xkbcommon::xkb;
+
+    /// The tests run with the US layout, whatever the system has
+    fn us() -> xkb::Keymap {
+        compile(("us".to_owned(), String::new())).unwrap()
+    }
+
+    #[test]
+    fn keysyms() {
+        let us = us();
+        let find_keysym = |name| keysym_key(&us, name);
+        assert_eq!(find_keysym("XF86AudioRaiseVolume"), Some((115, 0)));
+        assert_eq!(find_keysym("XF86Launch5"), Some((184, 0)));
+        assert_eq!(find_keysym("exclam"), Some((2, 1)));
+        assert_eq!(find_keysym("NotAKeysym"), None);
+    }
+
+    #[test]
+    fn text() {
+        let us = us();
+        let text_chords = |text| type_text(&us, text);
+        let chord = |modifiers: &[u16], key| KeyChord {
+            modifiers: modifiers.to_vec(),
+            sequence: vec![key],
+        };
+        assert_eq!(
+            text_chords("Hi!\n"),
+            Ok(vec![
+                chord(&[42], 35),
+                chord(&[], 23),
+                chord(&[42], 2),
+                chord(&[], 28),
+            ])
+        );
+        // not on the US layout
+        assert_eq!(
+            text_chords("→"),
+            Ok(vec![
+                chord(&[29, 42], 22),
+                chord(&[], 3),
+                chord(&[], 2),
+                chord(&[], 10),
+                chord(&[], 3),
+                chord(&[], 57),
+            ])
+        );
+    }
+
+    #[test]
+    fn layouts() {
+        assert_eq!(
+            parse_rules_names(
+                "_XKB_RULES_NAMES(STRING) = \"evdev\", \"pc105\", \"de,us\", \"nodeadkeys,\", \"\"\n"
+            ),
+            Some(("de,us".to_owned(), "nodeadkeys,".to_owned()))
+        );
+        assert_eq!(parse_rules_names("_XKB_RULES_NAMES:  not found.\n"), None);
+        let status = "   System Locale: LANG=de_DE.UTF-8\n       VC Keymap: de\n      X11 Layout: de\n       X11 Model: pc105\n     X11 Variant: nodeadkeys\n";
+        assert_eq!(
+            parse_localectl(status),
+            Some(("de".to_owned(), "nodeadkeys".to_owned()))
+        );
+        assert_eq!(parse_localectl("      X11 Layout: n/a\n"), None);
+    }
+}
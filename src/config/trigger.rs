@@ -1,15 +1,24 @@
 use crate::common::{Direction, PinchDirection, RotateDirection};
+use crate::error::Error;
+use crate::gesture_event::shape::Shapes;
 use crate::gesture_event::trigger as gesture;
 
 use serde::Deserialize;
+use std::rc::Rc;
 
-#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
+#[derive(PartialEq, Debug, Clone, Deserialize)]
 pub enum Trigger {
     Swipe {
         fingers: u32,
         direction: Direction,
         repeated: bool,
     },
+    EdgeSwipe {
+        fingers: u32,
+        edge: Direction,
+        direction: Direction,
+        repeated: bool,
+    },
     Shear {
         fingers: u32,
         direction: Direction,
@@ -28,54 +37,59 @@ pub enum Trigger {
     Hold {
         fingers: u32,
     },
-}
-
-#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
-struct Cardinal {
-    fingers: u32,
-    direction: Direction,
-    repeated: bool,
-}
-
-#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
-struct Pinch {
-    fingers: u32,
-    direction: PinchDirection,
-    repeated: bool,
-}
-
-#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
-struct Hold {
-    fingers: u32,
+    Tap {
+        fingers: u32,
+        count: u32,
+    },
+    MouseGesture {
+        strokes: Vec<Direction>,
+    },
+    Shape {
+        fingers: u32,
+        name: String,
+    },
 }
 
 impl Trigger {
     pub fn make(
         self,
-        swipe_distance: u32,
-        shear_distance: u32,
-        pinch_distance: f64,
-        rotate_distance: f64,
-    ) -> gesture::Trigger {
-        match self {
+        config: &super::Config,
+        shapes: &Rc<Shapes>,
+    ) -> Result<gesture::Trigger, Error> {
+        Ok(match self {
             Trigger::Swipe {
                 fingers,
                 direction,
                 repeated,
             } => gesture::Trigger::Swipe(gesture::CardinalTrigger {
-                fingers: fingers.try_into().expect("Too many fingers"),
+                fingers: convert_fingers(fingers)?,
                 direction,
-                distance: swipe_distance.try_into().expect("Incorrect distance"),
+                distance: config.swipe_distance().into(),
                 repeated,
             }),
+            Trigger::EdgeSwipe {
+                fingers,
+                edge,
+                direction,
+                repeated,
+            } => gesture::Trigger::EdgeSwipe(gesture::EdgeTrigger {
+                swipe: gesture::CardinalTrigger {
+                    fingers: convert_fingers(fingers)?,
+                    direction,
+                    distance: config.swipe_distance().into(),
+                    repeated,
+                },
+                edge,
+                margin: config.edge_margin(),
+            }),
             Trigger::Shear {
                 fingers,
                 direction,
                 repeated,
             } => gesture::Trigger::Shear(gesture::CardinalTrigger {
-                fingers: fingers.try_into().expect("Too many fingers"),
+                fingers: convert_fingers(fingers)?,
                 direction,
-                distance: shear_distance.try_into().expect("Incorrect distance"),
+                distance: config.shear_distance().into(),
                 repeated,
             }),
             Trigger::Pinch {
@@ -83,9 +97,9 @@ impl Trigger {
                 direction,
                 repeated,
             } => gesture::Trigger::Pinch(gesture::PinchTrigger {
-                fingers: fingers.try_into().expect("Too many fingers"),
+                fingers: convert_fingers(fingers)?,
                 direction,
-                scale: pinch_distance,
+                scale: config.pinch_distance(),
                 repeated,
             }),
             Trigger::Rotate {
@@ -93,15 +107,40 @@ impl Trigger {
                 direction,
                 repeated,
             } => gesture::Trigger::Rotate(gesture::RotateTrigger {
-                fingers: fingers.try_into().expect("Too many fingers"),
+                fingers: convert_fingers(fingers)?,
                 direction,
-                distance: rotate_distance,
+                distance: config.rotation_distance(),
                 repeated,
             }),
             Trigger::Hold { fingers } => gesture::Trigger::Hold(gesture::HoldTrigger {
-                fingers: fingers.try_into().expect("Too many fingers"),
+                fingers: convert_fingers(fingers)?,
                 time: 1, // Time not implemented currently
             }),
-        }
+            Trigger::Tap { fingers, count } => gesture::Trigger::Tap(gesture::TapTrigger {
+                fingers: convert_fingers(fingers)?,
+                count,
+                time: config.tap_time(),
+                interval: config.tap_interval(),
+            }),
+            Trigger::MouseGesture { strokes } => {
+                gesture::Trigger::Stroke(gesture::StrokeTrigger { strokes })
+            }
+            Trigger::Shape { fingers, name } => {
+                if !shapes.contains(&name) {
+                    return Err(Error::InvalidTrigger(format!("unknown shape: {}", name)));
+                }
+                gesture::Trigger::Shape(gesture::ShapeTrigger {
+                    fingers: convert_fingers(fingers)?,
+                    name,
+                    shapes: shapes.clone(),
+                })
+            }
+        })
     }
 }
+
+fn convert_fingers(fingers: u32) -> Result<i32, Error> {
+    fingers
+        .try_into()
+        .map_err(|_| Error::InvalidTrigger(format!("too many fingers: {}", fingers)))
+}
@@ -0,0 +1,96 @@
+//! Errors that prevent wzmach from starting or from continuing to run. Each of
+//! them knows how to explain itself to the user
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Permission denied when opening {0}")]
+    PermissionDenied(String),
+
+    #[error("Environment variable {0} is not set")]
+    MissingEnv(&'static str),
+
+    #[error("Environment variable {0} is not valid unicode")]
+    InvalidEnv(&'static str),
+
+    #[error("Invalid trigger: {0}")]
+    InvalidTrigger(String),
+
+    #[error("Invalid action: {0}")]
+    InvalidAction(String),
+
+    #[error("Could not read config {path}: {source}")]
+    ConfigRead {
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error("Could not parse config {path} at line {line}, column {column}: {message}\n{snippet}")]
+    ConfigParse {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+        snippet: String,
+    },
+
+    #[error("Problems in config:\n{}", .0.join("\n"))]
+    ConfigCheck(Vec<String>),
+
+    #[error("Config {0} includes itself")]
+    IncludeCycle(String),
+
+    #[error("Could not assign libinput to seat {0}")]
+    Seat(String),
+
+    #[error("Could not wait for input events: {0}")]
+    Poll(nix::Error),
+
+    #[error("Could not dispatch libinput events: {0}")]
+    Dispatch(std::io::Error),
+
+    #[error("Could not create virtual input device: {0}")]
+    Uinput(String),
+}
+
+impl Error {
+    /// Say which trigger the config error is about
+    pub fn in_trigger(self, name: &str) -> Self {
+        match self {
+            Error::InvalidTrigger(msg) => Error::InvalidTrigger(format!("{}: {}", name, msg)),
+            Error::InvalidAction(msg) => Error::InvalidAction(format!("{}: {}", name, msg)),
+            e => e,
+        }
+    }
+
+    /// Suggestion on what the user can do to fix the error, if we have one
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            Error::PermissionDenied(path) if path.starts_with("/dev/uinput") => Some(
+                "Make /dev/uinput writable by the input group, for example with a udev rule: \
+                 KERNEL==\"uinput\", GROUP=\"input\", MODE=\"0660\"",
+            ),
+            Error::PermissionDenied(_) => Some(
+                "Add yourself to the input group (sudo usermod -aG input $USER) and log in \
+                 again, or give wzmach the input group with setgid as described in README",
+            ),
+            Error::MissingEnv(_) | Error::InvalidEnv(_) => {
+                Some("Pass the config file explicitly with --config PATH")
+            }
+            Error::InvalidTrigger(_)
+            | Error::InvalidAction(_)
+            | Error::ConfigParse { .. }
+            | Error::ConfigCheck(_)
+            | Error::IncludeCycle(_) => Some("Check your config with `wzmach debug-config PATH`"),
+            Error::ConfigRead { .. } => {
+                Some("Put a config to ~/.config/wzmach/config.ron or pass it with --config PATH")
+            }
+            Error::Seat(_) => Some("Make sure udev is running and you are in a local session"),
+            Error::Poll(_) | Error::Dispatch(_) => {
+                Some("This can happen when input devices go away, try restarting wzmach")
+            }
+            Error::Uinput(_) => Some("Make sure the uinput kernel module is loaded"),
+        }
+    }
+}
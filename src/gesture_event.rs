@@ -1,30 +1,114 @@
 /// High-level gesture completion events, produced from observing low-level
 /// gesture events. Register your 'Trigger's for events and observe them
 /// triggered
+pub mod layer;
+pub mod shape;
 pub mod trigger;
-use trigger::{Origin, Trigger};
+use layer::Layers;
+use trigger::{Binding, Origin, Tap, Trigger};
 
-use crate::input_producer::event::{Gesture, InputEvent};
+use crate::common::AnyDirection;
+use crate::input_producer::event::{Gesture, InputEvent, Modifiers};
+use crate::input_producer::{DeviceName, Wakeup};
 use sorted_vec::SortedSet;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 /// Adapt low-level gesture events into high-level events by triggers
 pub struct EventAdapter<T: Iterator<Item = InputEvent>> {
     source: T,
-    triggers: Vec<Trigger>,
+    triggers: Vec<Binding>,
     /// When trigger has happened, adjust the event displacements for triggers in
     /// other directions
     adjust: Origin,
     triggered: SortedSet<usize>,
+    /// Latest holds that could be taps, to recognize multi-taps
+    taps: Vec<Tap>,
+    /// Only triggers of the active layer fire
+    layers: Layers,
+    /// How many times each trigger fired in the current gesture
+    repeats: HashMap<usize, u32>,
+    /// The previous event ended a gesture, so the next one starts a new one
+    gesture_over: bool,
+    device: DeviceName,
+    gestures: GestureCount,
+    /// Tap that fires only if no longer series of taps follows
+    pending: Option<PendingTap>,
+    /// Asks the source for a timeout event when the pending tap is due
+    wakeup: Wakeup,
+    /// Swipes held back until the gesture ends, as it could be a shape
+    drawing: Vec<usize>,
 }
 
+/// Tap trigger held back while another tap could make it a longer series
+#[derive(Debug, Clone, Copy)]
+struct PendingTap {
+    index: usize,
+    fingers: i32,
+    /// Longest time a tap can take
+    time: u32,
+    /// Latest time the next tap can begin
+    deadline: u32,
+    /// When to stop waiting and fire
+    expires: u32,
+}
+
+/// Number of gestures that ended so far, for the threads watching commands
+/// that should stop with the gesture that started them
+#[derive(Clone, Default)]
+pub struct GestureCount(Arc<AtomicU64>);
+
+impl GestureCount {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn increment(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Trigger that fired
+#[derive(PartialEq, Debug, Clone)]
+pub struct Fired {
+    /// Index of the trigger and its action
+    pub index: usize,
+    pub name: String,
+    pub context: Context,
+}
+
+/// What fired the trigger, for actions to pass on to the commands they run.
+/// Values that don't apply to the gesture, like scale of a swipe, are None
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Context {
+    /// Name of the trigger
+    pub trigger: String,
+    pub fingers: Option<i32>,
+    /// Direction of the trigger, like Up, In or Clockwise
+    pub direction: Option<String>,
+    /// Movement since the gesture began
+    pub dx: Option<f64>,
+    pub dy: Option<f64>,
+    pub scale: Option<f64>,
+    pub angle: Option<f64>,
+    /// Name of the device the gesture was made on
+    pub device: String,
+    /// How many times the trigger fired during the gesture, counting this one
+    pub repeat_count: u32,
+}
+
+/// No one taps more than this many times in a row
+const MAX_TAPS: usize = 8;
+
 impl<T: Iterator<Item = InputEvent>> EventAdapter<T> {
     /// Create event source from a low-level source. The created adapter will
     /// observe the given triggers. If the triggers conflict, the harder ones
     /// may never trigger
-    pub fn new(source: T, triggers: &Vec<Trigger>) -> Self {
+    pub fn new(source: T, triggers: &[Binding]) -> Self {
         EventAdapter {
             source,
-            triggers: (*triggers).clone(),
+            triggers: triggers.to_vec(),
             adjust: Origin {
                 x: 0.0,
                 y: 0.0,
@@ -32,21 +116,74 @@ impl<T: Iterator<Item = InputEvent>> EventAdapter<T> {
                 rotation: 0.0,
             },
             triggered: SortedSet::new(),
+            taps: Vec::new(),
+            layers: Layers::default(),
+            repeats: HashMap::new(),
+            gesture_over: false,
+            device: DeviceName::default(),
+            gestures: GestureCount::default(),
+            pending: None,
+            wakeup: Wakeup::default(),
+            drawing: Vec::new(),
         }
     }
 
+    /// Get a timeout event from the source when a held back tap is due
+    pub fn with_wakeup(mut self, wakeup: Wakeup) -> Self {
+        self.wakeup = wakeup;
+        self
+    }
+
+    /// Count ended gestures for the actions that watch them
+    pub fn with_gesture_count(mut self, gestures: GestureCount) -> Self {
+        self.gestures = gestures;
+        self
+    }
+
+    /// Tell actions the device that made the gesture, as the producer sees it
+    pub fn with_device_name(mut self, device: DeviceName) -> Self {
+        self.device = device;
+        self
+    }
+
+    /// Share the layer state with the actions that switch layers
+    pub fn with_layers(mut self, layers: Layers) -> Self {
+        self.layers = layers;
+        self
+    }
+
     /// Returns index of matched trigger
     fn adapt(&mut self, event: InputEvent) -> Vec<usize> {
-        let (gesture, ctime, ended) = match event {
-            InputEvent::Ongoing(g, t) => (g, t, false),
-            InputEvent::Ended(g, t) => (g, t, true),
-            InputEvent::Cancelled(_, t) => (Gesture::None, t, true),
+        let (gesture, ctime, modifiers, ended) = match event {
+            InputEvent::Ongoing(g, t, m) => (g, t, m, false),
+            InputEvent::Ended(g, t, m) => (g, t, m, true),
+            InputEvent::Cancelled(_, t, m) => (Gesture::None, t, m, true),
+            InputEvent::Timeout(t) => {
+                return match self.pending {
+                    Some(p) if t > p.expires => self.release_tap().into_iter().collect(),
+                    _ => Vec::new(),
+                };
+            }
         };
+        let released = self.continue_taps(&gesture, ctime, ended);
+        if self.gesture_over {
+            self.repeats.clear();
+        }
+        self.gesture_over = ended;
+        self.record_tap(&gesture, ctime, ended);
+        self.layers.expire();
         // first collect matching indicies that we will return from the function
-        let inds = self.triggers.iter().enumerate().filter_map(|(i, t)| {
-            match (&gesture, t) {
+        let inds = self
+            .triggers
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| modifiers_match(&self.triggers, b, modifiers))
+            .filter(|(_, b)| self.layers.is_active(b.layer.as_deref()))
+            .filter(|(_, b)| match (&gesture, &b.trigger) {
                 (Gesture::None, _) => false,
                 (Gesture::Swipe(gs), Trigger::Swipe(ts)) => ts.matches_swipe(gs, self.adjust),
+                (Gesture::Swipe(gs), Trigger::EdgeSwipe(te)) => te.matches(gs, self.adjust),
+                (Gesture::Swipe(gs), Trigger::Shape(ts)) => ended && ts.matches(gs),
                 (Gesture::Swipe(_), _) => false,
 
                 (Gesture::Pinch(gp), Trigger::Pinch(tp)) => tp.matches(gp, self.adjust.scale),
@@ -55,14 +192,17 @@ impl<T: Iterator<Item = InputEvent>> EventAdapter<T> {
                 (Gesture::Pinch(_), _) => false,
 
                 (Gesture::Hold(gh), Trigger::Hold(th)) => th.matches(gh, ctime),
+                (Gesture::Hold(_), Trigger::Tap(tt)) => ended && tt.matches(&self.taps),
                 (Gesture::Hold(_), _) => false,
-            }
-            .then(|| i)
-        });
+
+                (Gesture::Stroke(gs), Trigger::Stroke(ts)) => ended && ts.matches(gs),
+                (Gesture::Stroke(_), _) => false,
+            });
         // From them remove the ones that were triggered and are not repeated
-        let inds = inds
+        let mut inds = inds
+            .map(|(i, _)| i)
             .filter(|i| {
-                if !self.triggers[*i].repeated() {
+                if !self.triggers[*i].trigger.repeated() {
                     match self.triggered.find_or_insert(*i) {
                         sorted_vec::FindOrInsert::Found(_present_at) => false,
                         sorted_vec::FindOrInsert::Inserted(_inserted_at) => true,
@@ -72,6 +212,23 @@ impl<T: Iterator<Item = InputEvent>> EventAdapter<T> {
                 }
             })
             .collect::<Vec<usize>>();
+        // Hold back taps that could be the start of a longer series
+        let mut held = None;
+        inds.retain(|i| match self.longer_series(*i, modifiers) {
+            Some(interval) => {
+                held = Some((*i, interval));
+                false
+            }
+            None => true,
+        });
+        if let Some((index, interval)) = held {
+            self.hold_tap(index, interval, ctime);
+        }
+        let inds = released.into_iter().chain(inds).collect::<Vec<_>>();
+        let fired = self.hold_drawn(&gesture, &inds, ended);
+        for i in &fired {
+            *self.repeats.entry(*i).or_default() += 1;
+        }
         // Cleanup and adjustments
         if ended {
             // adjust to neutral when end
@@ -83,27 +240,152 @@ impl<T: Iterator<Item = InputEvent>> EventAdapter<T> {
             };
             // we can retrigger everything again
             self.triggered = sorted_vec::SortedSet::new();
+            self.layers.gesture_ended();
+            self.gestures.increment();
         } else {
             // Move origin for the next triggers in this gesture if something
             // triggered
-            if inds.len() != 0 {
+            if !inds.is_empty() {
                 self.move_origin(&gesture);
             }
             // We can retrigger cardinals in other directions
             let trigger_dirs = inds
                 .iter()
-                .map(|i| self.triggers[*i].direction())
+                .map(|i| self.triggers[*i].trigger.direction())
                 .filter(|i| i.is_some())
                 .collect::<Vec<_>>();
-            if trigger_dirs.len() != 0 {
+            if !trigger_dirs.is_empty() {
                 log::trace!("Triggered directions: {:?}", trigger_dirs);
                 self.triggered.mutate_vec(|ts| {
                     // retain only those directions that were triggered just now
-                    ts.retain(|i| trigger_dirs.contains(&self.triggers[*i].direction()))
+                    ts.retain(|i| trigger_dirs.contains(&self.triggers[*i].trigger.direction()))
+                });
+            }
+        }
+        fired
+    }
+
+    /// While a shape could be drawn with these fingers, hold back swipes that
+    /// aren't repeated. When the gesture ends, they fire unless it was a shape
+    fn hold_drawn(&mut self, gesture: &Gesture, inds: &[usize], ended: bool) -> Vec<usize> {
+        let drawn = match gesture {
+            Gesture::Swipe(s) => self.triggers.iter().any(|b| {
+                matches!(b.trigger, Trigger::Shape(ref t) if t.fingers == s.fingers)
+                    && self.layers.is_active(b.layer.as_deref())
+            }),
+            _ => false,
+        };
+        let (held, mut fired): (Vec<usize>, Vec<usize>) = inds.iter().partition(|i| {
+            drawn
+                && match self.triggers[**i].trigger {
+                    Trigger::Swipe(ref t) => !t.repeated,
+                    Trigger::EdgeSwipe(ref t) => !t.swipe.repeated,
+                    _ => false,
+                }
+        });
+        self.drawing.extend(held);
+        if ended {
+            let shape = fired
+                .iter()
+                .any(|i| matches!(self.triggers[*i].trigger, Trigger::Shape(_)));
+            if drawn && !shape {
+                fired.splice(0..0, self.drawing.drain(..));
+            }
+            self.drawing.clear();
+        }
+        fired
+    }
+
+    /// Longest interval of the tap triggers that could fire if more taps
+    /// follow the tap trigger at the index, None if there are none
+    fn longer_series(&self, index: usize, modifiers: Modifiers) -> Option<u32> {
+        let tap = match self.triggers[index].trigger {
+            Trigger::Tap(tt) => tt,
+            _ => return None,
+        };
+        self.triggers
+            .iter()
+            .filter(|b| self.layers.is_active(b.layer.as_deref()))
+            .filter(|b| modifiers_match(&self.triggers, b, modifiers))
+            .filter_map(|b| match b.trigger {
+                Trigger::Tap(tt) if tt.fingers == tap.fingers && tt.count > tap.count => {
+                    Some(tt.interval)
+                }
+                _ => None,
+            })
+            .max()
+    }
+
+    /// Decide what happens to the pending tap on a new gesture event: it's
+    /// dropped when the event is another tap of the series, kept while it
+    /// could be one, and released otherwise
+    fn continue_taps(&mut self, gesture: &Gesture, ctime: u32, ended: bool) -> Option<usize> {
+        let mut pending = self.pending?;
+        match gesture {
+            Gesture::Hold(h)
+                if h.fingers == pending.fingers && h.begin_time <= pending.deadline =>
+            {
+                let tap_over = h.begin_time.saturating_add(pending.time);
+                if ended && ctime <= tap_over {
+                    // the longer series takes over
+                    self.pending = None;
+                    self.wakeup.set(None);
+                    None
+                } else if !ended && ctime <= tap_over {
+                    // still could be a tap, wait until it can't
+                    pending.expires = pending.expires.max(tap_over);
+                    self.pending = Some(pending);
+                    self.wakeup.set(Some(pending.expires.saturating_add(1)));
+                    None
+                } else {
+                    self.release_tap()
+                }
+            }
+            _ => self.release_tap(),
+        }
+    }
+
+    fn hold_tap(&mut self, index: usize, interval: u32, ctime: u32) {
+        let Trigger::Tap(tt) = self.triggers[index].trigger else {
+            return;
+        };
+        log::debug!(
+            "Waiting for more taps before firing {}",
+            self.triggers[index].name
+        );
+        let deadline = ctime.saturating_add(interval);
+        self.pending = Some(PendingTap {
+            index,
+            fingers: tt.fingers,
+            time: tt.time,
+            deadline,
+            expires: deadline,
+        });
+        self.wakeup.set(Some(deadline.saturating_add(1)));
+    }
+
+    fn release_tap(&mut self) -> Option<usize> {
+        self.wakeup.set(None);
+        self.pending.take().map(|p| p.index)
+    }
+
+    /// Remember ended holds for tap triggers. Any movement in between breaks
+    /// the series
+    fn record_tap(&mut self, gesture: &Gesture, ctime: u32, ended: bool) {
+        match gesture {
+            Gesture::Hold(gh) if ended => {
+                if self.taps.len() >= MAX_TAPS {
+                    self.taps.remove(0);
+                }
+                self.taps.push(Tap {
+                    fingers: gh.fingers,
+                    begin: gh.begin_time,
+                    end: ctime,
                 });
             }
+            Gesture::Swipe(_) | Gesture::Pinch(_) | Gesture::Stroke(_) => self.taps.clear(),
+            Gesture::Hold(_) | Gesture::None => (),
         }
-        inds
     }
 
     /// Move origin based on what was triggered, so that next triggers execute
@@ -122,33 +404,157 @@ impl<T: Iterator<Item = InputEvent>> EventAdapter<T> {
                 self.adjust.scale = p.scale;
             }
             Gesture::Hold(_) => (),
+            Gesture::Stroke(_) => (),
         }
         log::trace!("Adjusted origin: {:?}", self.adjust);
     }
 }
 
+/// Bindings without modifiers don't care about them, unless another one for
+/// the same trigger wants exactly the held ones
+fn modifiers_match(bindings: &[Binding], binding: &Binding, modifiers: Modifiers) -> bool {
+    binding.modifiers == modifiers
+        || binding.modifiers.is_empty()
+            && !bindings
+                .iter()
+                .any(|b| b.modifiers == modifiers && b.trigger == binding.trigger)
+}
+
 impl<T: Iterator<Item = InputEvent>> Iterator for EventAdapter<T> {
-    type Item = Vec<usize>;
+    type Item = Vec<Fired>;
     fn next(&mut self) -> Option<Self::Item> {
         // should I maybe yield all the empty events?
         loop {
-            if let Some(event) = self.source.next() {
-                let r = self.adapt(event);
-                if r.len() != 0 {
-                    break Some(r);
+            let event = self.source.next()?;
+            let measured = match event {
+                InputEvent::Ongoing(ref g, ..)
+                | InputEvent::Ended(ref g, ..)
+                | InputEvent::Cancelled(ref g, ..) => Context::measure(g),
+                InputEvent::Timeout(_) => Context::default(),
+            };
+            let r = self.adapt(event);
+            if !r.is_empty() {
+                break Some(
+                    r.into_iter()
+                        .map(|index| {
+                            let binding = &self.triggers[index];
+                            // Held back taps fire on later events, which
+                            // tell nothing about the tap
+                            let measured = match binding.trigger {
+                                Trigger::Tap(tt) => Context {
+                                    fingers: Some(tt.fingers),
+                                    ..Context::default()
+                                },
+                                _ => measured.clone(),
+                            };
+                            Fired {
+                                index,
+                                name: binding.name.clone(),
+                                context: Context {
+                                    trigger: binding.name.clone(),
+                                    direction: binding.trigger.direction().map(|d| match d {
+                                        AnyDirection::Cardinal(d) => format!("{:?}", d),
+                                        AnyDirection::Pinch(d) => format!("{:?}", d),
+                                        AnyDirection::Rotate(d) => format!("{:?}", d),
+                                    }),
+                                    device: self.device.get(),
+                                    repeat_count: self.repeats.get(&index).copied().unwrap_or(1),
+                                    ..measured
+                                },
+                            }
+                        })
+                        .collect(),
+                );
+            }
+        }
+    }
+}
+
+impl Context {
+    /// The values that come from the gesture itself
+    fn measure(gesture: &Gesture) -> Context {
+        let mut context = Context::default();
+        match gesture {
+            Gesture::None | Gesture::Stroke(_) => (),
+            Gesture::Swipe(s) => {
+                context.fingers = Some(s.fingers);
+                context.dx = Some(s.dx);
+                context.dy = Some(s.dy);
+            }
+            Gesture::Pinch(p) => {
+                context.fingers = Some(p.fingers);
+                context.dx = Some(p.dx);
+                context.dy = Some(p.dy);
+                context.scale = Some(p.scale);
+                context.angle = Some(p.angle);
+            }
+            Gesture::Hold(h) => context.fingers = Some(h.fingers),
+        }
+        context
+    }
+
+    /// Values by their placeholder names, empty when they don't apply
+    pub fn values(&self) -> Vec<(&'static str, String)> {
+        let number = |x: Option<f64>| x.map(|x| format!("{:.2}", x)).unwrap_or_default();
+        vec![
+            ("trigger", self.trigger.clone()),
+            (
+                "fingers",
+                self.fingers.map(|f| f.to_string()).unwrap_or_default(),
+            ),
+            ("direction", self.direction.clone().unwrap_or_default()),
+            ("dx", number(self.dx)),
+            ("dy", number(self.dy)),
+            ("scale", number(self.scale)),
+            ("angle", number(self.angle)),
+            ("device", self.device.clone()),
+            ("repeat_count", self.repeat_count.to_string()),
+        ]
+    }
+
+    /// Environment variables for commands, like WZMACH_FINGERS
+    pub fn env(&self) -> Vec<(String, String)> {
+        self.values()
+            .into_iter()
+            .map(|(name, value)| (format!("WZMACH_{}", name.to_uppercase()), value))
+            .collect()
+    }
+
+    /// Replace placeholders like {fingers} in the text. Unknown ones stay
+    pub fn substitute(&self, text: &str) -> String {
+        let values = self.values();
+        let mut result = String::new();
+        let mut rest = text;
+        while let Some(open) = rest.find('{') {
+            result.push_str(&rest[..open]);
+            rest = &rest[open..];
+            let value = rest.find('}').and_then(|close| {
+                let (_, value) = values.iter().find(|(name, _)| *name == &rest[1..close])?;
+                Some((value, close))
+            });
+            match value {
+                Some((value, close)) => {
+                    result.push_str(value);
+                    rest = &rest[close + 1..];
+                }
+                None => {
+                    result.push('{');
+                    rest = &rest[1..];
                 }
             }
         }
+        result.push_str(rest);
+        result
     }
 }
 
 /// Iterator adapter interface for EventAdapter
 pub trait EventAdapterExt: Iterator<Item = InputEvent> + Sized {
-    fn adapt_events(self, triggers: &Vec<Trigger>) -> EventAdapter<Self>;
+    fn adapt_events(self, triggers: &[Binding]) -> EventAdapter<Self>;
 }
 
 impl<I: Iterator<Item = InputEvent>> EventAdapterExt for I {
-    fn adapt_events(self, triggers: &Vec<Trigger>) -> EventAdapter<Self> {
+    fn adapt_events(self, triggers: &[Binding]) -> EventAdapter<Self> {
         EventAdapter::new(self, triggers)
     }
 }
@@ -156,7 +562,7 @@ impl<I: Iterator<Item = InputEvent>> EventAdapterExt for I {
 #[cfg(test)]
 mod test {
     use crate::common::Direction;
-    use crate::gesture_event::trigger::{CardinalTrigger, Trigger};
+    use crate::gesture_event::trigger::{Binding, CardinalTrigger, TapTrigger, Trigger};
 
     #[test]
     fn swipe_up_down() {
@@ -173,8 +579,11 @@ mod test {
             repeated: false,
         });
         let mut adapter = super::EventAdapter::new(
-            crate::input_producer::GestureProducer::new(),
-            &vec![trigger_up, trigger_down],
+            std::iter::empty(),
+            &[
+                Binding::new("up", trigger_up),
+                Binding::new("down", trigger_down),
+            ],
         );
 
         use crate::input_producer::event::*;
@@ -184,8 +593,11 @@ mod test {
                 fingers: 3,
                 dx: 10.0,
                 dy: -101.0,
+                start: None,
+                path: Vec::new(),
             }),
             10,
+            Modifiers::default(),
         );
         let event_up = InputEvent::Ongoing(
             Gesture::Swipe(SwipeGesture {
@@ -193,8 +605,11 @@ mod test {
                 fingers: 3,
                 dx: -20.0,
                 dy: -202.0,
+                start: None,
+                path: Vec::new(),
             }),
             10,
+            Modifiers::default(),
         );
         let event_down = InputEvent::Ongoing(
             Gesture::Swipe(SwipeGesture {
@@ -202,19 +617,256 @@ mod test {
                 fingers: 3,
                 dx: 30.0,
                 dy: 10.0,
+                start: None,
+                path: Vec::new(),
             }),
             20,
+            Modifiers::default(),
         );
 
         let r = adapter.adapt(event_down.clone());
-        assert_eq!(r, Vec::new());
+        assert_eq!(r, Vec::<usize>::new());
         let r = adapter.adapt(event_up_half.clone());
-        assert_eq!(r, Vec::new());
+        assert_eq!(r, Vec::<usize>::new());
         let r = adapter.adapt(event_up.clone());
         assert_eq!(r, vec![0]);
         let r = adapter.adapt(event_up_half.clone());
-        assert_eq!(r, Vec::new());
+        assert_eq!(r, Vec::<usize>::new());
         let r = adapter.adapt(event_down.clone());
         assert_eq!(r, vec![1]);
     }
+
+    #[test]
+    fn draw() {
+        use crate::gesture_event::shape::{Shapes, Template};
+        use crate::gesture_event::trigger::ShapeTrigger;
+        use crate::input_producer::event::*;
+
+        let l = [(0.0, 0.0), (0.0, 100.0), (60.0, 100.0)];
+        let shapes = Shapes::new(vec![Template::new("L".to_owned(), &l).unwrap()], 0.8);
+        let down = Trigger::Swipe(CardinalTrigger {
+            fingers: 3,
+            direction: Direction::Down,
+            distance: 50.0,
+            repeated: false,
+        });
+        let shape = Trigger::Shape(ShapeTrigger {
+            fingers: 3,
+            name: "L".to_owned(),
+            shapes: std::rc::Rc::new(shapes),
+        });
+        let mut adapter = super::EventAdapter::new(
+            std::iter::empty(),
+            &[Binding::new("down", down), Binding::new("L", shape)],
+        );
+        let swipe = |path: &[(f64, f64)]| {
+            let &(dx, dy) = path.last().unwrap();
+            Gesture::Swipe(SwipeGesture {
+                begin_time: 0,
+                fingers: 3,
+                dx,
+                dy,
+                start: None,
+                path: path.to_vec(),
+            })
+        };
+        let ongoing = |path| InputEvent::Ongoing(swipe(path), 10, Modifiers::default());
+        let ended = |path| InputEvent::Ended(swipe(path), 20, Modifiers::default());
+
+        // drawing the shape doesn't fire the swipe it starts with
+        assert_eq!(adapter.adapt(ongoing(&l[..2])), Vec::<usize>::new());
+        assert_eq!(adapter.adapt(ended(&l)), vec![1]);
+        // a plain swipe fires when it ends
+        let line = [(0.0, 0.0), (0.0, 100.0), (0.0, 150.0)];
+        assert_eq!(adapter.adapt(ongoing(&line[..2])), Vec::<usize>::new());
+        assert_eq!(adapter.adapt(ended(&line)), vec![0]);
+    }
+
+    #[test]
+    fn double_tap() {
+        let tap = |count| {
+            Trigger::Tap(TapTrigger {
+                fingers: 3,
+                count,
+                time: 200,
+                interval: 300,
+            })
+        };
+        let mut adapter = super::EventAdapter::new(
+            std::iter::empty(),
+            &[
+                Binding::new("tap", tap(1)),
+                Binding::new("double tap", tap(2)),
+            ],
+        );
+
+        use crate::input_producer::event::*;
+        let hold = |begin_time, end_time| {
+            InputEvent::Ended(
+                Gesture::Hold(HoldGesture {
+                    begin_time,
+                    fingers: 3,
+                }),
+                end_time,
+                Modifiers::default(),
+            )
+        };
+
+        // the first tap of a double tap doesn't fire the single tap
+        assert_eq!(adapter.adapt(hold(0, 100)), Vec::<usize>::new());
+        assert_eq!(adapter.adapt(hold(200, 300)), vec![1]);
+        // third tap is neither single nor double
+        assert_eq!(adapter.adapt(hold(400, 500)), Vec::<usize>::new());
+        // too long after the previous one, so it's a new series, which fires
+        // once no other tap follows in time
+        assert_eq!(adapter.adapt(hold(1000, 1100)), Vec::<usize>::new());
+        assert_eq!(
+            adapter.adapt(InputEvent::Timeout(1300)),
+            Vec::<usize>::new()
+        );
+        assert_eq!(adapter.adapt(InputEvent::Timeout(1401)), vec![0]);
+        // too long to be a tap, so the tap before it fires
+        assert_eq!(adapter.adapt(hold(2000, 2100)), Vec::<usize>::new());
+        assert_eq!(adapter.adapt(hold(2200, 2500)), vec![0]);
+        assert_eq!(
+            adapter.adapt(InputEvent::Timeout(3000)),
+            Vec::<usize>::new()
+        );
+    }
+
+    #[test]
+    fn modifiers() {
+        use crate::gesture_event::trigger::HoldTrigger;
+        use crate::input_producer::event::*;
+        const LEFT_META: u32 = 125;
+
+        let hold = Trigger::Hold(HoldTrigger {
+            fingers: 3,
+            time: 1,
+        });
+        let meta = Modifiers::from_codes([LEFT_META]).unwrap();
+        let bindings = [
+            Binding::new("hold", hold.clone()),
+            Binding {
+                name: "meta hold".to_owned(),
+                trigger: hold,
+                modifiers: meta,
+                layer: None,
+            },
+        ];
+        let mut adapter = super::EventAdapter::new(std::iter::empty(), &bindings);
+        let event = |modifiers| {
+            InputEvent::Ended(
+                Gesture::Hold(HoldGesture {
+                    begin_time: 0,
+                    fingers: 3,
+                }),
+                100,
+                modifiers,
+            )
+        };
+
+        assert_eq!(adapter.adapt(event(Modifiers::default())), vec![0]);
+        assert_eq!(adapter.adapt(event(meta)), vec![1]);
+        // no binding wants shift, so the plain one fires
+        let meta_shift = Modifiers::from_codes([LEFT_META, 42]).unwrap();
+        assert_eq!(adapter.adapt(event(meta_shift)), vec![0]);
+        let shift = Modifiers::from_codes([42]).unwrap();
+        assert_eq!(adapter.adapt(event(shift)), vec![0]);
+        assert_eq!(Modifiers::from_codes([30]), Err(30));
+    }
+
+    #[test]
+    fn layers() {
+        use crate::common::LayerMode;
+        use crate::gesture_event::layer::Layers;
+        use crate::gesture_event::trigger::HoldTrigger;
+        use crate::input_producer::event::*;
+
+        let hold = |fingers| Trigger::Hold(HoldTrigger { fingers, time: 1 });
+        let bindings = [
+            Binding::new("base 3", hold(3)),
+            Binding::new("base 4", hold(4)),
+            Binding {
+                name: "media 3".to_owned(),
+                trigger: hold(3),
+                modifiers: Modifiers::default(),
+                layer: Some("media".to_owned()),
+            },
+        ];
+        let layers = Layers::default();
+        let mut adapter =
+            super::EventAdapter::new(std::iter::empty(), &bindings).with_layers(layers.clone());
+        let event = |fingers, ended: bool| {
+            let g = Gesture::Hold(HoldGesture {
+                begin_time: 0,
+                fingers,
+            });
+            if ended {
+                InputEvent::Ended(g, 100, Modifiers::default())
+            } else {
+                InputEvent::Ongoing(g, 100, Modifiers::default())
+            }
+        };
+
+        assert_eq!(adapter.adapt(event(3, true)), vec![0]);
+        layers.switch("media", LayerMode::Toggle);
+        assert_eq!(adapter.adapt(event(3, true)), vec![2]);
+        // base triggers don't fire in other layers
+        assert_eq!(adapter.adapt(event(4, true)), Vec::<usize>::new());
+        layers.switch("media", LayerMode::Toggle);
+        assert_eq!(adapter.adapt(event(3, true)), vec![0]);
+
+        // temporary layer ends with the gesture
+        adapter.adapt(event(4, false));
+        layers.switch("media", LayerMode::UntilGestureEnd);
+        assert_eq!(adapter.adapt(event(3, true)), vec![2]);
+        assert_eq!(layers.active(), None);
+    }
+
+    #[test]
+    fn context() {
+        use crate::input_producer::event::*;
+
+        let trigger = Trigger::Swipe(CardinalTrigger {
+            fingers: 3,
+            direction: Direction::Right,
+            distance: 100.0,
+            repeated: true,
+        });
+        let swipe = |dx| {
+            InputEvent::Ongoing(
+                Gesture::Swipe(SwipeGesture {
+                    begin_time: 0,
+                    fingers: 3,
+                    dx,
+                    dy: 5.0,
+                    start: None,
+                    path: Vec::new(),
+                }),
+                10,
+                Modifiers::default(),
+            )
+        };
+        let events = vec![swipe(110.0), swipe(220.0)];
+        let bindings = [Binding::new("right", trigger)];
+        let fired = super::EventAdapter::new(events.into_iter(), &bindings)
+            .flatten()
+            .map(|f| f.context)
+            .collect::<Vec<_>>();
+        assert_eq!(fired.len(), 2);
+        let context = &fired[1];
+        assert_eq!(context.repeat_count, 2);
+        assert_eq!(context.dx, Some(220.0));
+        assert_eq!(context.direction.as_deref(), Some("Right"));
+        assert_eq!(context.scale, None);
+
+        assert_eq!(
+            context.substitute("{trigger} {fingers}{unknown} {dx},{scale}} {"),
+            "right 3{unknown} 220.00,} {"
+        );
+        assert!(context
+            .env()
+            .contains(&("WZMACH_REPEAT_COUNT".to_owned(), "2".to_owned())));
+    }
 }
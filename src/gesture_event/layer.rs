@@ -0,0 +1,80 @@
+//! Layers are named sets of triggers. Triggers outside of any layer make up the
+//! base layer, and when another layer is active only its triggers fire. Layers
+//! are switched by actions, so the state is shared between them and the
+//! adapter.
+
+use crate::common::LayerMode;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Handle to the active layer. Clones share the state
+#[derive(Clone, Default)]
+pub struct Layers(Rc<RefCell<State>>);
+
+#[derive(Default)]
+struct State {
+    /// None is the base layer
+    active: Option<String>,
+    until: Until,
+}
+
+#[derive(Default)]
+enum Until {
+    /// Until switched by another action
+    #[default]
+    Switched,
+    GestureEnd,
+    Time(Instant),
+}
+
+impl Layers {
+    /// Activate the layer in the given mode. Toggling the active layer goes
+    /// back to the base one
+    pub fn switch(&self, layer: &str, mode: LayerMode) {
+        let mut state = self.0.borrow_mut();
+        if mode == LayerMode::Toggle && state.active.as_deref() == Some(layer) {
+            log::info!("Back to base layer");
+            *state = State::default();
+            return;
+        }
+        log::info!("Switched to layer {} ({:?})", layer, mode);
+        state.active = Some(layer.to_owned());
+        state.until = match mode {
+            LayerMode::Toggle => Until::Switched,
+            LayerMode::UntilGestureEnd => Until::GestureEnd,
+            LayerMode::Timeout(ms) => Until::Time(Instant::now() + Duration::from_millis(ms)),
+        };
+    }
+
+    /// Is this layer the active one. None is the base layer
+    pub fn is_active(&self, layer: Option<&str>) -> bool {
+        self.0.borrow().active.as_deref() == layer
+    }
+
+    /// Name of the active layer, None for the base one
+    pub fn active(&self) -> Option<String> {
+        self.0.borrow().active.clone()
+    }
+
+    /// Go back to the base layer if the time is up
+    pub(crate) fn expire(&self) {
+        let mut state = self.0.borrow_mut();
+        if matches!(state.until, Until::Time(t) if t <= Instant::now()) {
+            log::info!("Layer {:?} timed out, back to base layer", state.active);
+            *state = State::default();
+        }
+    }
+
+    /// Go back to the base layer if it was only for this gesture. Actions run
+    /// after the adapter, so a layer switched on the last event of a gesture
+    /// lasts until the end of the next one
+    pub(crate) fn gesture_ended(&self) {
+        let mut state = self.0.borrow_mut();
+        if matches!(state.until, Until::GestureEnd) {
+            log::info!("Gesture ended, back to base layer");
+            *state = State::default();
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Recognition of shapes drawn with a swipe, using the $1 unistroke recognizer
+//! from "Gestures without Libraries, Toolkits or Training" by Wobbrock, Wilson
+//! and Li. Unlike the original, orientation matters: a shape is only looked
+//! for with a small tilt, so that drawing an "L" doesn't give you a "7".
+
+/// Paths are resampled to this many points before comparing
+const POINTS: usize = 64;
+
+/// Paths are scaled to fit a square of this size
+const SQUARE: f64 = 250.0;
+
+/// How far the drawing can be tilted from the template, in radians
+const MAX_TILT: f64 = std::f64::consts::FRAC_PI_4;
+const TILT_PRECISION: f64 = 2.0 * std::f64::consts::PI / 180.0;
+
+/// Golden ratio for the search of the best tilt
+const PHI: f64 = 0.618_033_988_749_895;
+
+pub type Point = (f64, f64);
+
+/// A named shape to compare drawings against
+#[derive(PartialEq, Debug, Clone)]
+pub struct Template {
+    pub name: String,
+    points: Vec<Point>,
+}
+
+/// All known shapes and how similar a drawing must be to one of them
+#[derive(PartialEq, Debug, Clone)]
+pub struct Shapes {
+    templates: Vec<Template>,
+    /// From 0 to 1, where 1 is exactly the same
+    threshold: f64,
+}
+
+impl Template {
+    /// Returns None if the path is too short to tell its shape
+    pub fn new(name: String, path: &[Point]) -> Option<Self> {
+        Some(Template {
+            name,
+            points: normalize(path)?,
+        })
+    }
+}
+
+impl Shapes {
+    pub fn new(templates: Vec<Template>, threshold: f64) -> Self {
+        Shapes {
+            templates,
+            threshold,
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.templates.iter().any(|t| t.name == name)
+    }
+
+    /// Name of the shape most similar to the path, if it's similar enough
+    pub fn recognize(&self, path: &[Point]) -> Option<&str> {
+        let points = normalize(path)?;
+        let half_diagonal = 0.5 * (2.0 * SQUARE * SQUARE).sqrt();
+        let (template, distance) = self
+            .templates
+            .iter()
+            .map(|t| (t, best_distance(&points, &t.points)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+        let score = 1.0 - distance / half_diagonal;
+        log::debug!("Shape looks like {} with score {:.2}", template.name, score);
+        (score >= self.threshold).then_some(template.name.as_str())
+    }
+}
+
+/// Resample the path to evenly spaced points, scale it to the square and put
+/// its centroid to the origin. Returns None if the path is a single point
+pub fn normalize(path: &[Point]) -> Option<Vec<Point>> {
+    let points = resample(path)?;
+    let (min_x, max_x, min_y, max_y) = points.iter().fold(
+        (f64::MAX, f64::MIN, f64::MAX, f64::MIN),
+        |(x0, x1, y0, y1), &(x, y)| (x0.min(x), x1.max(x), y0.min(y), y1.max(y)),
+    );
+    // Keep straight lines straight instead of stretching them to a square
+    let size = (max_x - min_x).max(max_y - min_y);
+    let width = (max_x - min_x).max(size * 0.1);
+    let height = (max_y - min_y).max(size * 0.1);
+    let scaled = points
+        .iter()
+        .map(|&(x, y)| (x * SQUARE / width, y * SQUARE / height))
+        .collect::<Vec<_>>();
+    let (cx, cy) = centroid(&scaled);
+    Some(scaled.iter().map(|&(x, y)| (x - cx, y - cy)).collect())
+}
+
+fn resample(path: &[Point]) -> Option<Vec<Point>> {
+    let length = path.windows(2).map(|w| distance(w[0], w[1])).sum::<f64>();
+    if length <= f64::EPSILON {
+        return None;
+    }
+    let step = length / (POINTS - 1) as f64;
+    let mut points = vec![path[0]];
+    let mut walked = 0.0;
+    let mut prev = path[0];
+    for &next in &path[1..] {
+        let mut d = distance(prev, next);
+        while walked + d >= step && points.len() < POINTS {
+            let t = (step - walked) / d;
+            prev = (
+                prev.0 + t * (next.0 - prev.0),
+                prev.1 + t * (next.1 - prev.1),
+            );
+            points.push(prev);
+            d = distance(prev, next);
+            walked = 0.0;
+        }
+        walked += d;
+        prev = next;
+    }
+    // Rounding can leave us one point short
+    points.resize(POINTS, path[path.len() - 1]);
+    Some(points)
+}
+
+/// Distance to the template when tilted the best way, found with the golden
+/// section search
+fn best_distance(points: &[Point], template: &[Point]) -> f64 {
+    let mut a = -MAX_TILT;
+    let mut b = MAX_TILT;
+    let mut x1 = PHI * a + (1.0 - PHI) * b;
+    let mut f1 = path_distance(&rotate(points, x1), template);
+    let mut x2 = (1.0 - PHI) * a + PHI * b;
+    let mut f2 = path_distance(&rotate(points, x2), template);
+    while (b - a).abs() > TILT_PRECISION {
+        if f1 < f2 {
+            b = x2;
+            x2 = x1;
+            f2 = f1;
+            x1 = PHI * a + (1.0 - PHI) * b;
+            f1 = path_distance(&rotate(points, x1), template);
+        } else {
+            a = x1;
+            x1 = x2;
+            f1 = f2;
+            x2 = (1.0 - PHI) * a + PHI * b;
+            f2 = path_distance(&rotate(points, x2), template);
+        }
+    }
+    f1.min(f2)
+}
+
+fn rotate(points: &[Point], angle: f64) -> Vec<Point> {
+    let (sin, cos) = angle.sin_cos();
+    points
+        .iter()
+        .map(|&(x, y)| (x * cos - y * sin, x * sin + y * cos))
+        .collect()
+}
+
+fn path_distance(a: &[Point], b: &[Point]) -> f64 {
+    a.iter().zip(b).map(|(p, q)| distance(*p, *q)).sum::<f64>() / a.len() as f64
+}
+
+fn centroid(points: &[Point]) -> Point {
+    let n = points.len() as f64;
+    (
+        points.iter().map(|p| p.0).sum::<f64>() / n,
+        points.iter().map(|p| p.1).sum::<f64>() / n,
+    )
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    (b.0 - a.0).hypot(b.1 - a.1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Point, Shapes, Template};
+
+    /// Arc around the origin from one angle to another, in degrees
+    fn arc(from: f64, to: f64, radius: f64) -> Vec<Point> {
+        (0..=20)
+            .map(|i| {
+                let a = (from + (to - from) * i as f64 / 20.0).to_radians();
+                (radius * a.cos(), radius * a.sin())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn letters() {
+        // drawn from top right, counterclockwise on screen where y goes down
+        let c = arc(-45.0, -315.0, 100.0);
+        let l = vec![(0.0, 0.0), (0.0, 100.0), (60.0, 100.0)];
+        let shapes = Shapes::new(
+            vec![
+                Template::new("C".to_owned(), &c).unwrap(),
+                Template::new("L".to_owned(), &l).unwrap(),
+            ],
+            0.8,
+        );
+
+        // a smaller, sloppier C in a different place
+        let drawn = arc(-50.0, -300.0, 30.0)
+            .into_iter()
+            .map(|(x, y)| (x + 500.0, y * 1.1 + 20.0))
+            .collect::<Vec<_>>();
+        assert_eq!(shapes.recognize(&drawn), Some("C"));
+        let drawn = vec![(10.0, 10.0), (12.0, 200.0), (15.0, 210.0), (140.0, 205.0)];
+        assert_eq!(shapes.recognize(&drawn), Some("L"));
+        // L upside down is not an L
+        let drawn = vec![(0.0, 100.0), (0.0, 0.0), (-60.0, 0.0)];
+        assert_eq!(shapes.recognize(&drawn), None);
+        assert_eq!(shapes.recognize(&[(1.0, 1.0)]), None);
+    }
+}
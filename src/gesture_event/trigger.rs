@@ -3,15 +3,23 @@
 //! pub(crate): These triggers can also perform computations to see if events
 //! match them
 
+use super::shape::Shapes;
 use crate::common::{AnyDirection, Direction, PinchDirection, RotateDirection};
-use crate::input_producer::event::{HoldGesture, PinchGesture, SwipeGesture};
+
+use crate::input_producer::event::{
+    HoldGesture, Modifiers, PinchGesture, Position, StrokeGesture, SwipeGesture,
+};
+use std::rc::Rc;
 
 const VSLOPE: f64 = 1.0;
 const HSLOPE: f64 = 1.0 / VSLOPE;
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Trigger {
     Swipe(CardinalTrigger),
+    /// Swipe that starts near an edge of the device. Only works on devices
+    /// that report finger positions, like touchscreens
+    EdgeSwipe(EdgeTrigger),
     Pinch(PinchTrigger),
     /// Shear is when you move different fingers in different directions.
     /// Usually done by resting your digits and moving your thumb. The direction
@@ -23,9 +31,40 @@ pub enum Trigger {
     Rotate(RotateTrigger),
     /// Sent only when hold ended
     Hold(HoldTrigger),
+    /// Short holds one after another. Sent when the last tap ended
+    Tap(TapTrigger),
+    /// Mouse gesture, sent when the gesture button is released
+    Stroke(StrokeTrigger),
+    /// Swipe that draws a shape. Sent when the swipe ended
+    Shape(ShapeTrigger),
     // TODO: hold in progress. Need to track my own time, bleh
 }
 
+/// Trigger together with the conditions under which it can fire
+#[derive(PartialEq, Debug, Clone)]
+pub struct Binding {
+    /// Unique name to show to humans
+    pub name: String,
+    pub trigger: Trigger,
+    /// Keyboard modifiers that must be held, exactly these and no others. With
+    /// none, any can be held
+    pub modifiers: Modifiers,
+    /// Layer the trigger belongs to, None for the base layer
+    pub layer: Option<String>,
+}
+
+impl Binding {
+    /// Binding in the base layer without modifiers
+    pub fn new(name: impl Into<String>, trigger: Trigger) -> Self {
+        Binding {
+            name: name.into(),
+            trigger,
+            modifiers: Modifiers::default(),
+            layer: None,
+        }
+    }
+}
+
 /// Common struct for triggers in a certain direction over a certain distance:
 /// swipes and shears.
 /// Wow, why rust still has the same record problems that haskell does? Why
@@ -38,6 +77,15 @@ pub struct CardinalTrigger {
     pub repeated: bool,
 }
 
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct EdgeTrigger {
+    pub swipe: CardinalTrigger,
+    pub edge: Direction,
+    /// How far from the edge the swipe can start, as a fraction of the device
+    /// size
+    pub margin: f64,
+}
+
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub struct PinchTrigger {
     pub fingers: i32,
@@ -62,6 +110,38 @@ pub struct HoldTrigger {
     pub time: u32,
 }
 
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct TapTrigger {
+    pub fingers: i32,
+    pub count: u32,
+    /// Longest time in ms fingers can stay down
+    pub time: u32,
+    /// Longest time in ms between two taps
+    pub interval: u32,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct StrokeTrigger {
+    pub strokes: Vec<Direction>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ShapeTrigger {
+    pub fingers: i32,
+    pub name: String,
+    /// All shapes from the config, so that the drawing goes to the most similar
+    /// one and not to every one it resembles
+    pub shapes: Rc<Shapes>,
+}
+
+/// A hold that has ended, which could be a tap
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) struct Tap {
+    pub fingers: i32,
+    pub begin: u32,
+    pub end: u32,
+}
+
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub(crate) struct Origin {
     pub x: f64,
@@ -84,6 +164,17 @@ impl Direction {
     }
 }
 
+impl Direction {
+    fn near(&self, pos: Position, margin: f64) -> bool {
+        match self {
+            Direction::Up => pos.y <= margin,
+            Direction::Down => pos.y >= 1.0 - margin,
+            Direction::Left => pos.x <= margin,
+            Direction::Right => pos.x >= 1.0 - margin,
+        }
+    }
+}
+
 impl RotateDirection {
     fn matches(&self, sign: f64) -> bool {
         match self {
@@ -107,6 +198,15 @@ impl CardinalTrigger {
     }
 }
 
+impl EdgeTrigger {
+    pub(crate) fn matches(&self, gest: &SwipeGesture, o: Origin) -> bool {
+        self.swipe.matches_swipe(gest, o)
+            && gest
+                .start
+                .is_some_and(|pos| self.edge.near(pos, self.margin))
+    }
+}
+
 impl PinchTrigger {
     pub(crate) fn matches(&self, gest: &PinchGesture, origin: f64) -> bool {
         /*
@@ -143,27 +243,70 @@ impl HoldTrigger {
     }
 }
 
+impl TapTrigger {
+    /// Taps are latest last. Match when the latest taps make up a series of
+    /// exactly the right length, so that a triple tap doesn't also fire
+    /// double tap triggers at the end
+    pub(crate) fn matches(&self, taps: &[Tap]) -> bool {
+        let mut count = 0;
+        let mut next_begin: Option<u32> = None;
+        for tap in taps.iter().rev() {
+            if tap.fingers != self.fingers || tap.end.saturating_sub(tap.begin) > self.time {
+                break;
+            }
+            match next_begin {
+                Some(begin) if begin.saturating_sub(tap.end) > self.interval => break,
+                _ => (),
+            }
+            count += 1;
+            next_begin = Some(tap.begin);
+        }
+        count == self.count
+    }
+}
+
+impl StrokeTrigger {
+    pub(crate) fn matches(&self, gest: &StrokeGesture) -> bool {
+        self.strokes == gest.strokes
+    }
+}
+
+impl ShapeTrigger {
+    pub(crate) fn matches(&self, gest: &SwipeGesture) -> bool {
+        gest.fingers == self.fingers
+            && self.shapes.recognize(&gest.path) == Some(self.name.as_str())
+    }
+}
+
 /* Impl for generalized field access */
 
 impl Trigger {
     pub(crate) fn repeated(&self) -> bool {
         match self {
             Trigger::Swipe(s) => s.repeated,
+            Trigger::EdgeSwipe(e) => e.swipe.repeated,
             Trigger::Pinch(p) => p.repeated,
             Trigger::Shear(s) => s.repeated,
             Trigger::Rotate(r) => r.repeated,
             // you can't repeat holds, but repeated are simpler to handle
             Trigger::Hold(_) => true,
+            Trigger::Tap(_) => true,
+            Trigger::Stroke(_) => true,
+            Trigger::Shape(_) => true,
         }
     }
 
     pub(crate) fn direction(&self) -> Option<AnyDirection> {
         match self {
             Trigger::Swipe(s) => Some(AnyDirection::Cardinal(s.direction)),
+            Trigger::EdgeSwipe(e) => Some(AnyDirection::Cardinal(e.swipe.direction)),
             Trigger::Pinch(p) => Some(AnyDirection::Pinch(p.direction)),
             Trigger::Shear(s) => Some(AnyDirection::Cardinal(s.direction)),
             Trigger::Rotate(r) => Some(AnyDirection::Rotate(r.direction)),
             Trigger::Hold(_) => None,
+            Trigger::Tap(_) => None,
+            Trigger::Stroke(_) => None,
+            Trigger::Shape(_) => None,
         }
     }
 }
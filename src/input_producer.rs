@@ -2,52 +2,167 @@
 //! gesture events
 
 pub mod event;
+mod mouse;
+mod touch;
 
-use event::{Gesture, InputEvent};
+use event::{Gesture, InputEvent, Modifiers};
+use mouse::MouseRecognizer;
+use touch::TouchRecognizer;
 
+use crate::common::MouseButton;
+use crate::error::Error;
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
 use std::fs::{File, OpenOptions};
 use std::os::unix::{
     fs::OpenOptionsExt,
     io::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
 };
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
 
+use input::event::{
+    gesture::GestureEventTrait,
+    keyboard::{KeyboardEvent, KeyboardEventTrait},
+    pointer::PointerEventTrait,
+    touch::TouchEventTrait,
+    DeviceEvent, EventTrait,
+};
 use input::{Libinput, LibinputInterface};
-use libc::{O_RDONLY, O_RDWR, O_WRONLY};
+use libc::{O_ACCMODE, O_RDONLY, O_RDWR, O_WRONLY};
 
 /* Libinput thing */
 
-struct Interface;
+struct Interface {
+    access: Rc<RefCell<AccessLog>>,
+}
+
+/// What happened when libinput asked us to open devices. Libinput silently
+/// skips the devices it can't open, so this is the only way to find out
+#[derive(Default)]
+struct AccessLog {
+    opened: usize,
+    denied: Vec<PathBuf>,
+    /// Files currently open, so that we can grab the devices
+    fds: HashMap<PathBuf, RawFd>,
+}
 
 /// Interface that just tries to open files directly. This requires running as
 /// root or using sgid and the group "input"
 impl LibinputInterface for Interface {
     fn open_restricted(&mut self, path: &Path, flags: i32) -> Result<RawFd, i32> {
-        OpenOptions::new()
+        let mode = flags & O_ACCMODE;
+        let result = OpenOptions::new()
             .custom_flags(flags)
-            .read((flags & O_RDONLY != 0) | (flags & O_RDWR != 0))
-            .write((flags & O_WRONLY != 0) | (flags & O_RDWR != 0))
-            .open(path)
-            .map(|file| file.into_raw_fd())
-            .map_err(|err| err.raw_os_error().unwrap())
+            .read(mode == O_RDONLY || mode == O_RDWR)
+            .write(mode == O_WRONLY || mode == O_RDWR)
+            .open(path);
+        let mut access = self.access.borrow_mut();
+        match result {
+            Ok(file) => {
+                access.opened += 1;
+                let fd = file.into_raw_fd();
+                access.fds.insert(path.to_owned(), fd);
+                Ok(fd)
+            }
+            Err(err) => {
+                log::debug!("Could not open {}: {}", path.display(), err);
+                if err.kind() == std::io::ErrorKind::PermissionDenied {
+                    access.denied.push(path.to_owned());
+                }
+                Err(err.raw_os_error().unwrap_or(libc::EIO))
+            }
+        }
     }
     fn close_restricted(&mut self, fd: RawFd) {
+        self.access.borrow_mut().fds.retain(|_, f| *f != fd);
         unsafe {
             File::from_raw_fd(fd);
         }
     }
 }
 
+// EVIOCGRAB from linux/input.h: take exclusive access to an evdev device, so
+// that its events don't reach anyone else
+nix::ioctl_write_int!(eviocgrab, b'E', 0x90);
+
 /* The gesture iterator */
 
+/// Libinput gives absolute positions in integer pixels, we ask for this many
+/// pixels to get a precise fraction
+const POSITION_SCALE: u32 = 100_000;
+
+/// If suspended time grew by more than this between two polls, we consider
+/// that the system went to sleep
+const SLEEP_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Name of the device that produced the latest gesture event, shared with
+/// whoever wants to know it while the producer is iterated
+#[derive(Clone, Default)]
+pub struct DeviceName(Rc<RefCell<String>>);
+
+impl DeviceName {
+    pub fn get(&self) -> String {
+        self.0.borrow().clone()
+    }
+
+    fn set(&self, device: &input::Device) {
+        let name = device.name();
+        if *self.0.borrow() != name {
+            *self.0.borrow_mut() = name.to_owned();
+        }
+    }
+}
+
+/// Time in libinput milliseconds at which whoever iterates the producer wants
+/// an event, even if there is no input by then
+#[derive(Clone, Default)]
+pub struct Wakeup(Rc<Cell<Option<u32>>>);
+
+impl Wakeup {
+    pub fn set(&self, time: Option<u32>) {
+        self.0.set(time);
+    }
+
+    fn get(&self) -> Option<u32> {
+        self.0.get()
+    }
+}
+
 /// The producer of gesture input. Create and then use as iterator.
 pub struct GestureProducer {
     input: input::Libinput,
+    access: Rc<RefCell<AccessLog>>,
     current: Gesture,
+    /// Device that produces the current gesture
+    current_device: Option<input::Device>,
+    /// Time of the latest gesture event, to timestamp cancellations that
+    /// libinput doesn't tell us about
+    last_time: u32,
+    /// Gestures on touchscreens, which we recognize ourselves
+    touch: TouchRecognizer,
+    /// Mouse gestures, when enabled
+    mouse: Option<MouseRecognizer>,
+    /// Mice we took exclusive access to for mouse gestures
+    grabbed: Vec<input::Device>,
+    /// Keyboard modifiers currently held
+    modifiers: Modifiers,
+    /// Events produced by one libinput event but not yet given out
+    queue: VecDeque<InputEvent>,
+    /// Devices libinput currently observes
+    devices: Vec<input::Device>,
+    /// How long the system was suspended in total when we last checked
+    slept: Option<Duration>,
+    /// Set when the input stream broke and the iterator stopped
+    error: Option<Error>,
+    device_name: DeviceName,
+    wakeup: Wakeup,
 }
 
 impl GestureProducer {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self, Error> {
         // Gesture sequences always start with a LIBINPUT_EVENT_GESTURE_FOO_START
         // event. All following gesture events will be of the
         // LIBINPUT_EVENT_GESTURE_FOO_UPDATE type until a
@@ -57,35 +172,286 @@ impl GestureProducer {
         // But in the end it doesn't matter (explicitly) for this
         // implementation. Maybe I implicitly depend on it somewhere..
 
-        let mut input = Libinput::new_with_udev(Interface);
-        input.udev_assign_seat("seat0").unwrap();
+        let access = Rc::new(RefCell::new(AccessLog::default()));
+        let mut input = Libinput::new_with_udev(Interface {
+            access: access.clone(),
+        });
+        input
+            .udev_assign_seat("seat0")
+            .map_err(|()| Error::Seat("seat0".to_owned()))?;
+
+        {
+            let access = access.borrow();
+            if access.opened == 0 {
+                if let Some(path) = access.denied.first() {
+                    return Err(Error::PermissionDenied(path.display().to_string()));
+                }
+                log::warn!("No input devices found");
+            }
+        }
         log::debug!("Created input connection");
 
-        GestureProducer {
+        let mut producer = GestureProducer {
             input,
+            access,
             current: Gesture::None,
+            current_device: None,
+            last_time: 0,
+            touch: TouchRecognizer::new(),
+            mouse: None,
+            grabbed: Vec::new(),
+            modifiers: Modifiers::default(),
+            queue: VecDeque::new(),
+            devices: Vec::new(),
+            slept: suspended_time(),
+            error: None,
+            device_name: DeviceName::default(),
+            wakeup: Wakeup::default(),
+        };
+        // Libinput has already queued the devices it found, collect them so
+        // they are known before the first gesture
+        producer.input.dispatch().map_err(Error::Dispatch)?;
+        while let Some(event) = producer.input.next() {
+            if let input::Event::Device(ev) = event {
+                producer.handle_device(ev);
+            }
+        }
+        Ok(producer)
+    }
+
+    /// Recognize gestures drawn with a mouse while holding the button. This
+    /// takes exclusive access to the mice and forwards the rest of their input
+    /// through a virtual one
+    pub fn with_mouse_gestures(mut self, button: MouseButton) -> Result<Self, Error> {
+        self.mouse = Some(MouseRecognizer::new(button)?);
+        for device in self.devices.clone() {
+            self.grab(&device);
         }
+        Ok(self)
+    }
+
+    /// Why the iterator stopped producing events, if it did
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    /// Devices that are currently connected and observed
+    pub fn devices(&self) -> &[input::Device] {
+        &self.devices
     }
 
-    fn poll_events(&mut self) {
+    /// Some connected device reports where fingers touch it
+    pub fn has_touchscreen(&self) -> bool {
+        self.devices
+            .iter()
+            .any(|d| d.has_capability(input::DeviceCapability::Touch))
+    }
+
+    /// Name of the device of the latest gesture, updated as events are produced
+    pub fn device_name(&self) -> DeviceName {
+        self.device_name.clone()
+    }
+
+    /// Wakeup time that the consumer can set to get a timeout event
+    pub fn wakeup(&self) -> Wakeup {
+        self.wakeup.clone()
+    }
+
+    fn poll_events(&mut self) -> Result<(), Error> {
         use nix::poll::PollFlags;
         let pollfd = nix::poll::PollFd::new(self.input.as_raw_fd(), PollFlags::POLLIN);
-        nix::poll::poll(&mut [pollfd], -1).unwrap();
-        self.input.dispatch().unwrap();
+        // Event times wrap around, so the difference does too
+        let timeout = match (self.wakeup.get(), input_time()) {
+            (Some(wakeup), Some(now)) => (wakeup.wrapping_sub(now) as i32).max(0),
+            _ => -1,
+        };
+        match nix::poll::poll(&mut [pollfd], timeout) {
+            Ok(0) if timeout >= 0 => {
+                self.wakeup.set(None);
+                let now = input_time().unwrap_or(self.last_time);
+                self.queue.push_back(InputEvent::Timeout(now));
+            }
+            Ok(_) | Err(nix::errno::Errno::EINTR) => (),
+            Err(e) => return Err(Error::Poll(e)),
+        }
+        self.check_sleep();
+        self.input.dispatch().map_err(Error::Dispatch)
+    }
+
+    /// After the system wakes up, devices can be in any state: fingers lifted
+    /// while we were asleep, or devices swapped. Reopen everything so that
+    /// libinput starts from scratch; removing devices also drops the gesture
+    /// in progress
+    fn check_sleep(&mut self) {
+        let slept = suspended_time();
+        if let (Some(before), Some(now)) = (self.slept, slept) {
+            if now.saturating_sub(before) > SLEEP_THRESHOLD {
+                log::info!("Woke up after {:?} of sleep", now - before);
+                // Keys could have been released while we slept
+                self.modifiers = Modifiers::default();
+                self.input.suspend();
+                if self.input.resume().is_err() {
+                    log::error!("Could not resume libinput after sleep");
+                }
+            }
+        }
+        self.slept = slept;
+    }
+
+    /// Take a mouse for mouse gestures, if it is one
+    fn grab(&mut self, device: &input::Device) {
+        use input::DeviceCapability;
+        let button = match self.mouse {
+            Some(ref mouse) => mouse.button(),
+            None => return,
+        };
+        let is_mouse = device.has_capability(DeviceCapability::Pointer)
+            && !device.has_capability(DeviceCapability::Gesture)
+            && !device.has_capability(DeviceCapability::Touch)
+            && !device.has_capability(DeviceCapability::Keyboard)
+            && device.pointer_has_button(button.code()) == Ok(true)
+            && device.name() != mouse::VIRTUAL_MOUSE
+            && device.name() != crate::action::VIRTUAL_POINTER;
+        if !is_mouse {
+            return;
+        }
+        // Safety: the udev device is only used to look up the path here
+        let path = unsafe { device.udev_device() }.and_then(|d| d.devnode().map(|p| p.to_owned()));
+        let fd = path.and_then(|p| self.access.borrow().fds.get(&p).copied());
+        let grabbed = match fd {
+            Some(fd) => unsafe { eviocgrab(fd, 1) }.map(|_| ()),
+            None => Err(nix::errno::Errno::EBADF),
+        };
+        match grabbed {
+            Ok(()) => {
+                log::info!("Using {} for mouse gestures", device.name());
+                self.grabbed.push(device.clone());
+            }
+            Err(e) => log::warn!("Could not grab {}: {}", device.name(), e),
+        }
+    }
+
+    /// Cancels the gesture in progress if it was produced by the removed device
+    fn handle_device(&mut self, event: DeviceEvent) {
+        let device = event.device();
+        match event {
+            DeviceEvent::Added(_) => {
+                log::info!("Device added: {} ({})", device.name(), device.sysname());
+                self.grab(&device);
+                self.devices.push(device);
+            }
+            DeviceEvent::Removed(_) => {
+                log::info!("Device removed: {} ({})", device.name(), device.sysname());
+                self.devices.retain(|d| *d != device);
+                if self.current_device.as_ref() == Some(&device) {
+                    self.current_device = None;
+                    match std::mem::replace(&mut self.current, Gesture::None) {
+                        Gesture::None => (),
+                        g => {
+                            log::debug!("Dropping gesture of removed device: {:?}", g);
+                            self.enqueue([InputEvent::Cancelled(
+                                g,
+                                self.last_time,
+                                self.modifiers,
+                            )]);
+                        }
+                    }
+                }
+                if self.grabbed.contains(&device) {
+                    self.grabbed.retain(|d| *d != device);
+                    let cancelled = self.mouse.as_mut().and_then(|m| m.cancel(self.last_time));
+                    self.enqueue(cancelled);
+                }
+                if device.has_capability(input::DeviceCapability::Touch) {
+                    let cancelled = self.touch.cancel(self.last_time);
+                    self.enqueue(cancelled);
+                }
+            }
+            _ => log::warn!("Device event from the future"),
+        }
+        log::debug!(
+            "Current devices: {:?}",
+            self.devices.iter().map(|d| d.name()).collect::<Vec<_>>()
+        );
+    }
+
+    /// Queue events with the modifiers held now, as they may change before
+    /// the events are taken from the queue
+    fn enqueue(&mut self, events: impl IntoIterator<Item = InputEvent>) {
+        let modifiers = self.modifiers;
+        self.queue
+            .extend(events.into_iter().map(|e| e.with_modifiers(modifiers)));
     }
 }
 
+/// Current time the way libinput timestamps events, in milliseconds of the
+/// monotonic clock
+fn input_time() -> Option<u32> {
+    use nix::time::{clock_gettime, ClockId};
+    let monotonic = clock_gettime(ClockId::CLOCK_MONOTONIC).ok()?;
+    Some(Duration::from(monotonic).as_millis() as u32)
+}
+
+/// Time the system spent suspended since boot. Monotonic clock stops during
+/// sleep and boot time clock doesn't, so their difference is the time asleep
+fn suspended_time() -> Option<Duration> {
+    use nix::time::{clock_gettime, ClockId};
+    let boot = clock_gettime(ClockId::CLOCK_BOOTTIME).ok()?;
+    let monotonic = clock_gettime(ClockId::CLOCK_MONOTONIC).ok()?;
+    Duration::from(boot).checked_sub(monotonic.into())
+}
+
 impl Iterator for GestureProducer {
     type Item = InputEvent;
     fn next(&mut self) -> Option<Self::Item> {
         loop {
+            if let Some(event) = self.queue.pop_front() {
+                break Some(event);
+            }
             match self.input.next() {
                 Some(input::Event::Gesture(gest)) => {
+                    let device = gest.device();
                     let state = self.current.update(&gest);
-                    break Some(InputEvent::from_state(state, &self.current));
+                    self.device_name.set(&device);
+                    if self.current_device.as_ref() != Some(&device) {
+                        self.current_device = Some(device);
+                    }
+                    self.last_time = gest.time();
+                    let event = InputEvent::from_state(state, &self.current);
+                    break Some(event.with_modifiers(self.modifiers));
                 }
+                Some(input::Event::Pointer(ev)) if self.grabbed.contains(&ev.device()) => {
+                    self.last_time = ev.time();
+                    self.device_name.set(&ev.device());
+                    if let Some(ref mut mouse) = self.mouse {
+                        let events = mouse.update(&ev);
+                        self.enqueue(events);
+                    }
+                }
+                Some(input::Event::Keyboard(KeyboardEvent::Key(ev))) => {
+                    // Our own virtual keyboard presses modifiers in actions,
+                    // those are not the user's
+                    if ev.device().name() != crate::action::VIRTUAL_KEYBOARD {
+                        // Count the key on all keyboards, in case it's held on
+                        // two of them
+                        self.modifiers.set(ev.key(), ev.seat_key_count() > 0);
+                    }
+                }
+                Some(input::Event::Touch(ev)) => {
+                    self.last_time = ev.time();
+                    self.device_name.set(&ev.device());
+                    let events = self.touch.update(&ev);
+                    self.enqueue(events);
+                }
+                Some(input::Event::Device(ev)) => self.handle_device(ev),
                 Some(_) => (),
-                None => self.poll_events(),
+                None => {
+                    if let Err(e) = self.poll_events() {
+                        log::error!("{}", e);
+                        self.error = Some(e);
+                        break None;
+                    }
+                }
             }
         }
     }
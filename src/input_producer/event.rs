@@ -2,6 +2,8 @@
 //! in gestures. It also provides routines for converting them and for updating
 //! the gesture state.
 
+use crate::common::Direction;
+
 use input::event::gesture::{
     GestureEndEvent, GestureEventCoordinates, GestureEventTrait, GesturePinchEventTrait,
     GesturePinchUpdateEvent,
@@ -13,6 +15,7 @@ pub enum Gesture {
     Swipe(SwipeGesture),
     Pinch(PinchGesture),
     Hold(HoldGesture),
+    Stroke(StrokeGesture),
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -21,6 +24,39 @@ pub struct SwipeGesture {
     pub fingers: i32,
     pub dx: f64,
     pub dy: f64,
+    /// Where fingers were when the swipe started. Touchpads don't tell us
+    /// where the fingers are, so it's only known for touchscreens
+    pub start: Option<Position>,
+    /// Points the fingers went through, relative to where they started, in
+    /// the same units as dx and dy. Long swipes are thinned out to keep at most
+    /// MAX_PATH_POINTS of them
+    pub path: Vec<(f64, f64)>,
+}
+
+/// Shapes are compared at a much lower resolution, so this loses nothing
+const MAX_PATH_POINTS: usize = 256;
+
+impl SwipeGesture {
+    /// Add the current position to the path, dropping every other point when
+    /// it's full
+    pub fn record(&mut self) {
+        if self.path.len() >= MAX_PATH_POINTS {
+            let mut i = 0;
+            self.path.retain(|_| {
+                i += 1;
+                i % 2 == 1
+            });
+        }
+        self.path.push((self.dx, self.dy));
+    }
+}
+
+/// Position on the surface of a device: 0.0 is the left or top edge, and 1.0 is
+/// the right or bottom edge
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -39,6 +75,14 @@ pub struct HoldGesture {
     pub fingers: i32,
 }
 
+/// Mouse movement while holding the gesture button
+#[derive(PartialEq, Debug, Clone)]
+pub struct StrokeGesture {
+    pub begin_time: u32,
+    /// Directions the mouse moved in, in order, without repetitions
+    pub strokes: Vec<Direction>,
+}
+
 #[derive(PartialEq, Debug)]
 pub enum GestureState {
     /// Arg is current event time
@@ -61,6 +105,8 @@ impl Gesture {
                         fingers: gest.finger_count(),
                         dx: 0.0,
                         dy: 0.0,
+                        start: None,
+                        path: vec![(0.0, 0.0)],
                     });
                     GestureState::Ongoing(gest.time())
                 }
@@ -129,6 +175,7 @@ impl Gesture {
             Gesture::Swipe(ref mut swipe) => {
                 swipe.dx += upd.dx();
                 swipe.dy += upd.dy();
+                swipe.record();
             }
             Gesture::Pinch(ref mut pinch) => {
                 pinch.dx += upd.dx();
@@ -165,20 +212,100 @@ impl Gesture {
 }
 
 /// The event itself that you can observe.
-/// Second arg is latest time for event
+/// Second arg is latest time for event, third is keyboard modifiers held at
+/// that time
 #[derive(PartialEq, Debug, Clone)]
 pub enum InputEvent {
-    Ongoing(Gesture, u32),
-    Ended(Gesture, u32),
-    Cancelled(Gesture, u32),
+    Ongoing(Gesture, u32, Modifiers),
+    Ended(Gesture, u32, Modifiers),
+    Cancelled(Gesture, u32, Modifiers),
+    /// Nothing happened until the time asked for with a wakeup
+    Timeout(u32),
 }
 
 impl InputEvent {
     pub(crate) fn from_state(state: GestureState, current: &Gesture) -> Self {
+        let none = Modifiers::default();
         match state {
-            GestureState::Ongoing(time) => InputEvent::Ongoing(current.clone(), time),
-            GestureState::Ended(g, t) => InputEvent::Ended(g, t),
-            GestureState::Cancelled(g, t) => InputEvent::Cancelled(g, t),
+            GestureState::Ongoing(time) => InputEvent::Ongoing(current.clone(), time, none),
+            GestureState::Ended(g, t) => InputEvent::Ended(g, t, none),
+            GestureState::Cancelled(g, t) => InputEvent::Cancelled(g, t, none),
+        }
+    }
+
+    /// Same event with the given modifiers held
+    pub(crate) fn with_modifiers(mut self, modifiers: Modifiers) -> Self {
+        match self {
+            InputEvent::Ongoing(_, _, ref mut m)
+            | InputEvent::Ended(_, _, ref mut m)
+            | InputEvent::Cancelled(_, _, ref mut m) => *m = modifiers,
+            InputEvent::Timeout(_) => (),
+        }
+        self
+    }
+}
+
+/// Evdev codes of keys that count as modifiers: control, shift, alt and meta,
+/// left and right
+const MODIFIER_KEYS: [u32; 8] = [29, 97, 42, 54, 56, 100, 125, 126];
+
+/// Set of keyboard modifiers, telling left and right ones apart
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    /// Modifiers from a list of evdev key codes. Returns the first code that
+    /// is not a modifier key as error
+    pub fn from_codes(codes: impl IntoIterator<Item = u32>) -> Result<Self, u32> {
+        let mut modifiers = Modifiers::default();
+        for code in codes {
+            if !modifiers.set(code, true) {
+                return Err(code);
+            }
+        }
+        Ok(modifiers)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Mark key as pressed or released. Returns false if it's not a modifier
+    pub fn set(&mut self, code: u32, pressed: bool) -> bool {
+        match MODIFIER_KEYS.iter().position(|k| *k == code) {
+            Some(i) => {
+                if pressed {
+                    self.0 |= 1 << i;
+                } else {
+                    self.0 &= !(1 << i);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SwipeGesture, MAX_PATH_POINTS};
+
+    #[test]
+    fn long_path() {
+        let mut swipe = SwipeGesture {
+            begin_time: 0,
+            fingers: 3,
+            dx: 0.0,
+            dy: 0.0,
+            start: None,
+            path: vec![(0.0, 0.0)],
+        };
+        for _ in 0..10_000 {
+            swipe.dx += 1.0;
+            swipe.record();
         }
+        assert!(swipe.path.len() <= MAX_PATH_POINTS);
+        assert_eq!(swipe.path.first(), Some(&(0.0, 0.0)));
+        assert_eq!(swipe.path.last(), Some(&(10_000.0, 0.0)));
     }
 }
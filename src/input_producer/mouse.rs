@@ -0,0 +1,287 @@
+//! Libinput reports mouse movement, but knows nothing of mouse gestures. While
+//! the gesture button is held, this module follows where the mouse goes and
+//! produces stroke gestures. So that the button and the strokes don't reach
+//! other programs, the mice are grabbed and everything else they do is passed
+//! on through a virtual mouse.
+
+use super::event::{Gesture, InputEvent, Modifiers, StrokeGesture};
+use crate::action::{create_mouse, send_scroll};
+use crate::common::{Direction, MouseButton};
+use crate::error::Error;
+
+use input::event::pointer::{
+    Axis, ButtonState, PointerEvent, PointerEventTrait, PointerScrollEvent, PointerScrollWheelEvent,
+};
+use uinput::event::relative::Position;
+
+/// Name of the virtual mouse, so that we can recognize and skip it among the
+/// devices libinput finds
+pub(crate) const VIRTUAL_MOUSE: &str = "wzmach_mouse";
+
+/// How far the mouse needs to travel in one direction to make a stroke, in
+/// unaccelerated pointer units
+const STROKE_DISTANCE: f64 = 50.0;
+
+const EV_KEY: i32 = 0x01;
+
+/// Smooth scrolling, like with a trackpoint or by holding a button, comes in
+/// pointer units, and compositors count 15 of them as one wheel detent of 120
+const V120_PER_UNIT: f64 = 120.0 / 15.0;
+
+/// Evdev codes of mouse buttons, from BTN_MISC to BTN_TASK, which the virtual
+/// mouse has all of to forward whatever the real mice have
+const BUTTONS: std::ops::RangeInclusive<u16> = 0x100..=0x117;
+
+/// Turns mouse movement with the held button into gestures, and forwards the
+/// rest of the mouse input
+pub(crate) struct MouseRecognizer {
+    button: MouseButton,
+    device: uinput::Device,
+    /// Set while the gesture button is held
+    stroke: Option<Stroke>,
+    /// Part of motion too small to send yet
+    motion: (f64, f64),
+    /// High resolution scroll not yet sent as whole detents
+    wheel: (i32, i32),
+    /// Part of smooth scrolling too small to send yet, in 120ths of a detent
+    smooth: (f64, f64),
+}
+
+/// Mouse gesture in progress
+#[derive(PartialEq, Debug)]
+struct Stroke {
+    begin_time: u32,
+    /// Movement since the last direction was decided
+    dx: f64,
+    dy: f64,
+    strokes: Vec<Direction>,
+}
+
+impl MouseRecognizer {
+    pub(crate) fn new(button: MouseButton) -> Result<Self, Error> {
+        let device = create_mouse(VIRTUAL_MOUSE, &BUTTONS.collect::<Vec<_>>())?;
+        log::debug!("Created virtual mouse");
+        Ok(MouseRecognizer {
+            button,
+            device,
+            stroke: None,
+            motion: (0.0, 0.0),
+            wheel: (0, 0),
+            smooth: (0.0, 0.0),
+        })
+    }
+
+    pub(crate) fn button(&self) -> MouseButton {
+        self.button
+    }
+
+    /// Handle an event from a grabbed mouse
+    pub(crate) fn update(&mut self, event: &PointerEvent) -> Option<InputEvent> {
+        let r = match event {
+            PointerEvent::Motion(ev) => {
+                let (dx, dy) = (ev.dx_unaccelerated(), ev.dy_unaccelerated());
+                match self.stroke {
+                    Some(ref mut stroke) => {
+                        stroke.motion(dx, dy);
+                        Ok(None)
+                    }
+                    None => self.forward_motion(dx, dy).map(|()| None),
+                }
+            }
+            PointerEvent::Button(ev) => {
+                let pressed = ev.button_state() == ButtonState::Pressed;
+                if ev.button() == self.button.code() {
+                    self.gesture_button(pressed, ev.time())
+                } else {
+                    self.forward_button(ev.button(), pressed).map(|()| None)
+                }
+            }
+            PointerEvent::ScrollWheel(ev) => self.forward_wheel(ev).map(|()| None),
+            PointerEvent::ScrollContinuous(ev) => self.forward_smooth(ev).map(|()| None),
+            PointerEvent::ScrollFinger(ev) => self.forward_smooth(ev).map(|()| None),
+            _ => Ok(None),
+        };
+        r.unwrap_or_else(|e| {
+            log::error!("Could not forward mouse input: {}", e);
+            None
+        })
+    }
+
+    /// Drop the gesture in progress
+    pub(crate) fn cancel(&mut self, time: u32) -> Option<InputEvent> {
+        let stroke = self.stroke.take()?;
+        log::debug!("Dropping mouse gesture");
+        Some(InputEvent::Cancelled(
+            stroke.gesture(),
+            time,
+            Modifiers::default(),
+        ))
+    }
+
+    fn gesture_button(
+        &mut self,
+        pressed: bool,
+        time: u32,
+    ) -> Result<Option<InputEvent>, uinput::Error> {
+        if pressed {
+            self.stroke = Some(Stroke::new(time));
+            return Ok(None);
+        }
+        match self.stroke.take() {
+            Some(stroke) if !stroke.strokes.is_empty() => Ok(Some(InputEvent::Ended(
+                stroke.gesture(),
+                time,
+                Modifiers::default(),
+            ))),
+            // Not a gesture, just a click
+            _ => {
+                let button = self.button.code() as i32;
+                self.device.write(EV_KEY, button, 1)?;
+                self.device.synchronize()?;
+                self.device.write(EV_KEY, button, 0)?;
+                self.device.synchronize()?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn forward_button(&mut self, code: u32, pressed: bool) -> Result<(), uinput::Error> {
+        if BUTTONS.contains(&(code as u16)) {
+            self.device.write(EV_KEY, code as i32, pressed as i32)?;
+            self.device.synchronize()
+        } else {
+            log::debug!("Not forwarding unknown mouse button {:#x}", code);
+            Ok(())
+        }
+    }
+
+    fn forward_motion(&mut self, dx: f64, dy: f64) -> Result<(), uinput::Error> {
+        let x = send_whole(&mut self.motion.0, dx);
+        let y = send_whole(&mut self.motion.1, dy);
+        if x != 0 {
+            self.device.send(Position::X, x)?;
+        }
+        if y != 0 {
+            self.device.send(Position::Y, y)?;
+        }
+        self.device.synchronize()
+    }
+
+    fn forward_wheel(&mut self, ev: &PointerScrollWheelEvent) -> Result<(), uinput::Error> {
+        let value = |axis| {
+            if ev.has_axis(axis) {
+                ev.scroll_value_v120(axis).round() as i32
+            } else {
+                0
+            }
+        };
+        // Libinput scrolls down with positive values, evdev scrolls up
+        let v = -value(Axis::Vertical);
+        let h = value(Axis::Horizontal);
+        send_scroll(&mut self.device, v, h, &mut self.wheel)?;
+        self.device.synchronize()
+    }
+
+    fn forward_smooth(&mut self, ev: &impl PointerScrollEvent) -> Result<(), uinput::Error> {
+        let value = |axis| {
+            if ev.has_axis(axis) {
+                ev.scroll_value(axis)
+            } else {
+                0.0
+            }
+        };
+        let v = smooth_v120(&mut self.smooth.0, -value(Axis::Vertical));
+        let h = smooth_v120(&mut self.smooth.1, value(Axis::Horizontal));
+        if v == 0 && h == 0 {
+            return Ok(());
+        }
+        send_scroll(&mut self.device, v, h, &mut self.wheel)?;
+        self.device.synchronize()
+    }
+}
+
+impl Stroke {
+    fn new(begin_time: u32) -> Self {
+        Stroke {
+            begin_time,
+            dx: 0.0,
+            dy: 0.0,
+            strokes: Vec::new(),
+        }
+    }
+
+    /// Accumulate motion, and once it's long enough decide where it went
+    fn motion(&mut self, dx: f64, dy: f64) {
+        self.dx += dx;
+        self.dy += dy;
+        if self.dx.hypot(self.dy) < STROKE_DISTANCE {
+            return;
+        }
+        let direction = if self.dx.abs() > self.dy.abs() {
+            if self.dx > 0.0 {
+                Direction::Right
+            } else {
+                Direction::Left
+            }
+        } else if self.dy > 0.0 {
+            Direction::Down
+        } else {
+            Direction::Up
+        };
+        if self.strokes.last() != Some(&direction) {
+            self.strokes.push(direction);
+        }
+        self.dx = 0.0;
+        self.dy = 0.0;
+    }
+
+    fn gesture(self) -> Gesture {
+        Gesture::Stroke(StrokeGesture {
+            begin_time: self.begin_time,
+            strokes: self.strokes,
+        })
+    }
+}
+
+/// Take the whole part of the accumulated value and keep the rest
+fn send_whole(acc: &mut f64, delta: f64) -> i32 {
+    *acc += delta;
+    let whole = acc.trunc();
+    *acc -= whole;
+    whole as i32
+}
+
+/// Smooth scrolling in pointer units to whole 120ths of a detent
+fn smooth_v120(acc: &mut f64, units: f64) -> i32 {
+    send_whole(acc, units * V120_PER_UNIT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{smooth_v120, Stroke};
+    use crate::common::Direction;
+
+    #[test]
+    fn strokes() {
+        let mut stroke = Stroke::new(0);
+        // small jitter doesn't make a stroke
+        stroke.motion(10.0, -5.0);
+        assert_eq!(stroke.strokes, vec![]);
+        for _ in 0..10 {
+            stroke.motion(2.0, 20.0);
+        }
+        stroke.motion(60.0, 0.0);
+        stroke.motion(60.0, 10.0);
+        assert_eq!(stroke.strokes, vec![Direction::Down, Direction::Right]);
+    }
+
+    #[test]
+    fn smooth_scroll() {
+        let mut acc = 0.0;
+        assert_eq!(smooth_v120(&mut acc, 15.0), 120);
+        // small steps add up instead of getting lost
+        let sent = (0..20).map(|_| smooth_v120(&mut acc, 0.05)).sum::<i32>();
+        assert_eq!(sent, 8);
+        assert_eq!(smooth_v120(&mut acc, -1.0), -8);
+    }
+}
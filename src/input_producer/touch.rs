@@ -0,0 +1,345 @@
+//! Libinput only recognizes gestures on touchpads, and for touchscreens it
+//! gives out raw touch points. This module tracks those points and produces
+//! the same gestures that touchpads do, so the rest of the engine doesn't need
+//! to know the difference.
+
+use super::event::{
+    Gesture, HoldGesture, InputEvent, Modifiers, PinchGesture, Position, SwipeGesture,
+};
+use super::POSITION_SCALE;
+
+use std::collections::BTreeMap;
+
+use input::event::touch::{TouchEvent, TouchEventPosition, TouchEventSlot, TouchEventTrait};
+
+/// Touchscreens report positions in millimeters, while touchpad gestures are
+/// measured in something like pixels. This makes the default swipe distance of
+/// 100 about 25mm on the screen
+const UNITS_PER_MM: f64 = 4.0;
+
+/// How far fingers need to move before a hold becomes a swipe or a pinch
+const MOTION_THRESHOLD_MM: f64 = 3.0;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct Touch {
+    /// Position in mm, to measure distances
+    mm: Point,
+    /// Position relative to the screen, to find edges
+    relative: Position,
+}
+
+/// Centroid and spread of fingers on the screen
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct Shape {
+    center: Point,
+    /// Average distance from fingers to the center
+    spread: f64,
+}
+
+/// Turns touch points into gestures. Create one and feed it all touch events
+pub(crate) struct TouchRecognizer {
+    /// Fingers on the screen by their seat slot
+    touches: BTreeMap<u32, Touch>,
+    /// Finger positions when the current gesture started
+    start: BTreeMap<u32, Touch>,
+    current: Gesture,
+    /// Set when a gesture ended by lifting a finger, and we wait for the rest
+    /// of them to lift before starting a new one
+    finished: bool,
+}
+
+impl TouchRecognizer {
+    pub(crate) fn new() -> Self {
+        TouchRecognizer {
+            touches: BTreeMap::new(),
+            start: BTreeMap::new(),
+            current: Gesture::None,
+            finished: false,
+        }
+    }
+
+    /// Update the state with libinput data. Gesture events are only produced
+    /// on frames, when libinput has given out all the points that moved
+    pub(crate) fn update(&mut self, event: &TouchEvent) -> Vec<InputEvent> {
+        match event {
+            TouchEvent::Down(ev) => self.touch(ev.seat_slot(), touch_of(ev)),
+            TouchEvent::Motion(ev) => self.touch(ev.seat_slot(), touch_of(ev)),
+            TouchEvent::Up(ev) => self.lift(ev.seat_slot()),
+            TouchEvent::Cancel(ev) => return self.cancel(ev.time()),
+            TouchEvent::Frame(ev) => return self.frame(ev.time()),
+            _ => log::warn!("Touch event from the future"),
+        }
+        Vec::new()
+    }
+
+    /// Drop all fingers and the gesture in progress
+    pub(crate) fn cancel(&mut self, time: u32) -> Vec<InputEvent> {
+        self.touches.clear();
+        self.start.clear();
+        self.finished = false;
+        match std::mem::replace(&mut self.current, Gesture::None) {
+            Gesture::None => Vec::new(),
+            g => vec![InputEvent::Cancelled(g, time, Modifiers::default())],
+        }
+    }
+
+    fn touch(&mut self, slot: u32, touch: Touch) {
+        self.touches.insert(slot, touch);
+    }
+
+    fn lift(&mut self, slot: u32) {
+        self.touches.remove(&slot);
+    }
+
+    fn frame(&mut self, time: u32) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        if self.touches.is_empty() {
+            // All fingers lifted: whatever was going on has ended successfully
+            self.start.clear();
+            self.finished = false;
+            if let Some(g) = self.take_current() {
+                events.push(InputEvent::Ended(g, time, Modifiers::default()));
+            }
+            return events;
+        }
+        if self.finished {
+            return events;
+        }
+
+        let same_fingers = self.touches.keys().eq(self.start.keys());
+        let lifted_some = self.touches.keys().all(|k| self.start.contains_key(k));
+        if same_fingers {
+            self.update_gesture(time, &mut events);
+        } else if lifted_some {
+            // Fingers never lift at exactly the same time, so the first one to
+            // lift ends the gesture
+            self.finished = true;
+            if let Some(g) = self.take_current() {
+                events.push(InputEvent::Ended(g, time, Modifiers::default()));
+            }
+        } else {
+            // New fingers landed, start over with all of them
+            if let Some(g) = self.take_current() {
+                events.push(InputEvent::Cancelled(g, time, Modifiers::default()));
+            }
+            self.start = self.touches.clone();
+            self.current = Gesture::Hold(HoldGesture {
+                begin_time: time,
+                fingers: self.touches.len() as i32,
+            });
+            events.push(InputEvent::Ongoing(
+                self.current.clone(),
+                time,
+                Modifiers::default(),
+            ));
+        }
+        events
+    }
+
+    fn update_gesture(&mut self, time: u32, events: &mut Vec<InputEvent>) {
+        let fingers = self.touches.len() as i32;
+        let before = shape(self.start.values().map(|t| &t.mm));
+        let now = shape(self.touches.values().map(|t| &t.mm));
+        let dx = (now.center.x - before.center.x) * UNITS_PER_MM;
+        let dy = (now.center.y - before.center.y) * UNITS_PER_MM;
+        let scale = if before.spread > f64::EPSILON {
+            now.spread / before.spread
+        } else {
+            1.0
+        };
+        let angle = self.rotation(before.center, now.center);
+
+        match self.current {
+            Gesture::Hold(ref hold) => {
+                let moved = dx.hypot(dy) / UNITS_PER_MM;
+                let spread = (now.spread - before.spread).abs();
+                let turned = angle.to_radians().abs() * now.spread;
+                if moved.max(spread).max(turned) < MOTION_THRESHOLD_MM {
+                    return;
+                }
+                events.push(InputEvent::Cancelled(
+                    self.current.clone(),
+                    time,
+                    Modifiers::default(),
+                ));
+                let begin_time = hold.begin_time;
+                self.current = if fingers >= 2 && spread.max(turned) > moved {
+                    Gesture::Pinch(PinchGesture {
+                        begin_time,
+                        fingers,
+                        scale,
+                        angle,
+                        dx,
+                        dy,
+                    })
+                } else {
+                    Gesture::Swipe(SwipeGesture {
+                        begin_time,
+                        fingers,
+                        dx,
+                        dy,
+                        start: Some(self.start_position()),
+                        path: vec![(0.0, 0.0), (dx, dy)],
+                    })
+                };
+            }
+            Gesture::Swipe(ref mut swipe) => {
+                swipe.dx = dx;
+                swipe.dy = dy;
+                swipe.record();
+            }
+            Gesture::Pinch(ref mut pinch) => {
+                pinch.scale = scale;
+                pinch.angle = angle;
+                pinch.dx = dx;
+                pinch.dy = dy;
+            }
+            Gesture::Stroke(_) | Gesture::None => return,
+        }
+        events.push(InputEvent::Ongoing(
+            self.current.clone(),
+            time,
+            Modifiers::default(),
+        ));
+    }
+
+    /// Average rotation of fingers around the center since gesture start, in
+    /// degrees. Positive is clockwise, same as libinput
+    fn rotation(&self, before: Point, now: Point) -> f64 {
+        let turns = self
+            .start
+            .iter()
+            .filter_map(|(slot, from)| {
+                let from = from.mm;
+                let to = self.touches.get(slot)?.mm;
+                let a = (from.y - before.y).atan2(from.x - before.x);
+                let b = (to.y - now.y).atan2(to.x - now.x);
+                let mut turn = (b - a).to_degrees();
+                if turn > 180.0 {
+                    turn -= 360.0;
+                } else if turn < -180.0 {
+                    turn += 360.0;
+                }
+                Some(turn)
+            })
+            .collect::<Vec<_>>();
+        if turns.len() < 2 {
+            0.0
+        } else {
+            turns.iter().sum::<f64>() / turns.len() as f64
+        }
+    }
+
+    /// Center of the fingers at the start of the gesture
+    fn start_position(&self) -> Position {
+        let count = self.start.len().max(1) as f64;
+        Position {
+            x: self.start.values().map(|t| t.relative.x).sum::<f64>() / count,
+            y: self.start.values().map(|t| t.relative.y).sum::<f64>() / count,
+        }
+    }
+
+    fn take_current(&mut self) -> Option<Gesture> {
+        match std::mem::replace(&mut self.current, Gesture::None) {
+            Gesture::None => None,
+            g => Some(g),
+        }
+    }
+}
+
+fn touch_of<T: TouchEventPosition>(event: &T) -> Touch {
+    Touch {
+        mm: Point {
+            x: event.x(),
+            y: event.y(),
+        },
+        relative: Position {
+            x: event.x_transformed(POSITION_SCALE) / f64::from(POSITION_SCALE),
+            y: event.y_transformed(POSITION_SCALE) / f64::from(POSITION_SCALE),
+        },
+    }
+}
+
+fn shape<'a>(points: impl Iterator<Item = &'a Point> + Clone) -> Shape {
+    let count = points.clone().count().max(1) as f64;
+    let center = Point {
+        x: points.clone().map(|p| p.x).sum::<f64>() / count,
+        y: points.clone().map(|p| p.y).sum::<f64>() / count,
+    };
+    let spread = points
+        .map(|p| (p.x - center.x).hypot(p.y - center.y))
+        .sum::<f64>()
+        / count;
+    Shape { center, spread }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Point, Touch, TouchRecognizer};
+    use crate::input_producer::event::{Gesture, InputEvent, Position};
+
+    /// Touch on a 100x100mm screen
+    fn at(x: f64, y: f64) -> Touch {
+        Touch {
+            mm: Point { x, y },
+            relative: Position {
+                x: x / 100.0,
+                y: y / 100.0,
+            },
+        }
+    }
+
+    #[test]
+    fn swipe_and_pinch() {
+        let mut rec = TouchRecognizer::new();
+        rec.touch(0, at(10.0, 10.0));
+        rec.touch(1, at(30.0, 10.0));
+        let r = rec.frame(0);
+        assert!(matches!(
+            r[..],
+            [InputEvent::Ongoing(Gesture::Hold(_), 0, _)]
+        ));
+
+        // both fingers move up together
+        rec.touch(0, at(10.0, 0.0));
+        rec.touch(1, at(30.0, 0.0));
+        let r = rec.frame(10);
+        assert!(matches!(
+            r[0],
+            InputEvent::Cancelled(Gesture::Hold(_), 10, _)
+        ));
+        match &r[1] {
+            InputEvent::Ongoing(Gesture::Swipe(s), 10, _) => {
+                assert_eq!(s.fingers, 2);
+                assert!(s.dy < -39.0 && s.dx.abs() < 0.1);
+                assert_eq!(s.start, Some(Position { x: 0.2, y: 0.1 }));
+            }
+            e => panic!("expected swipe, got {:?}", e),
+        }
+        rec.lift(0);
+        rec.lift(1);
+        let r = rec.frame(20);
+        assert!(matches!(
+            r[..],
+            [InputEvent::Ended(Gesture::Swipe(_), 20, _)]
+        ));
+
+        // fingers move apart
+        rec.touch(2, at(10.0, 10.0));
+        rec.touch(3, at(30.0, 10.0));
+        rec.frame(30);
+        rec.touch(2, at(0.0, 10.0));
+        rec.touch(3, at(40.0, 10.0));
+        let r = rec.frame(40);
+        match &r[1] {
+            InputEvent::Ongoing(Gesture::Pinch(p), 40, _) => assert!((p.scale - 2.0).abs() < 0.01),
+            e => panic!("expected pinch, got {:?}", e),
+        }
+    }
+}
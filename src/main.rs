@@ -3,6 +3,7 @@ use bpaf::{construct, Parser};
 mod action;
 mod common;
 mod config;
+mod error;
 mod gesture_event;
 mod input_producer;
 
@@ -10,8 +11,9 @@ mod input_producer;
 enum Opts {
     Run { config_path: Option<String> },
     DebugConfig { path: String },
-    DebugGestures,
+    DebugGestures { config_path: Option<String> },
     DebugEvents,
+    TrainShape { name: String },
 }
 
 fn opts_parser() -> bpaf::OptionParser<Opts> {
@@ -22,11 +24,15 @@ fn opts_parser() -> bpaf::OptionParser<Opts> {
         .help("Parse config file and check it for errors")
         .map(|path| Opts::DebugConfig { path });
 
-    let debug_gestures = bpaf::pure(Opts::DebugGestures)
+    let debug_gestures = bpaf::long("config")
+        .help("Use triggers from this config instead of all possible ones. Only layer switching actions are executed")
+        .argument("PATH")
+        .optional()
         .to_options()
         .descr("If you see nothing you need to `export RUST_LOG=debug` or `trace`")
         .command("debug-gestures")
-        .help("Print all completed gestures but execute no actions");
+        .help("Print all completed gestures but execute no actions")
+        .map(|config_path| Opts::DebugGestures { config_path });
 
     let debug_events = bpaf::pure(Opts::DebugEvents)
         .to_options()
@@ -34,13 +40,20 @@ fn opts_parser() -> bpaf::OptionParser<Opts> {
         .command("debug-events")
         .help("Print all incoming libinput gesture events and execute nothing");
 
+    let train_shape = bpaf::positional("NAME")
+        .to_options()
+        .descr("Draw the shape with a swipe and put the printed text into `shapes` in the config")
+        .command("train-shape")
+        .help("Record a shape for shape triggers")
+        .map(|name| Opts::TrainShape { name });
+
     let run = bpaf::long("config")
         .help("Path to a config file to use instead of default")
         .argument("PATH")
         .optional()
         .map(|config_path| Opts::Run { config_path });
 
-    (construct!([debug_gestures, debug_events, debug_config, run]))
+    (construct!([debug_gestures, debug_events, debug_config, train_shape, run]))
         .to_options()
         .descr("Touchpad gesture engine")
 }
@@ -55,79 +68,181 @@ fn main() {
 
     match parse_opts() {
         Opts::DebugConfig { path } => match config::Config::load(path) {
-            Ok(c) => println!("Load successful:\n{:?}", c),
+            Ok(c) => {
+                for warning in c.warnings() {
+                    println!("Warning: {}", warning);
+                }
+                println!("Load successful:\n{:?}", c)
+            }
             Err(e) => println!("Error during loading:\n{}", e),
         },
 
-        Opts::DebugGestures => debug_events(),
+        Opts::DebugGestures { config_path: None } => {
+            if let Err(e) = debug_events() {
+                debug_error(e)
+            }
+        }
+
+        Opts::DebugGestures {
+            config_path: Some(path),
+        } => {
+            if let Err(e) = config::Config::load(path).and_then(debug_config_gestures) {
+                debug_error(e)
+            }
+        }
 
-        Opts::DebugEvents => {
-            let producer = input_producer::GestureProducer::new();
-            log::debug!("Created input connection");
-            for event in producer {
-                log::debug!("update: {:?}", event);
+        Opts::TrainShape { name } => {
+            if let Err(e) = train_shape(&name) {
+                debug_error(e)
             }
         }
 
-        Opts::Run { config_path } => match load_config(config_path) {
-            Ok(x) => run(x),
+        Opts::DebugEvents => match input_producer::GestureProducer::new() {
+            Ok(mut producer) => {
+                log::debug!("Created input connection");
+                for device in producer.devices() {
+                    log::debug!("device: {} ({})", device.name(), device.sysname());
+                }
+                for event in producer.by_ref() {
+                    log::debug!("update: {:?}", event);
+                }
+                if let Some(e) = producer.take_error() {
+                    debug_error(e)
+                }
+            }
+            Err(e) => debug_error(e),
+        },
+
+        Opts::Run { config_path } => match load_config(config_path).and_then(run) {
+            Ok(()) => (),
             Err(e) => startup_error(e),
         },
     }
 }
 
-fn load_config(mb_path: Option<String>) -> Result<config::Config, std::io::Error> {
-    let config_path = mb_path.unwrap_or_else(|| {
-        let home = std::env::var_os("HOME").unwrap().into_string().unwrap();
-        let config_home = std::env::var_os("XDG_CONFIG_HOME")
-            .map(|x| x.into_string().unwrap())
-            .unwrap_or_else(|| home + "/.config");
-        let config_dir = config_home + "/wzmach/";
-
-        let local_path = config_dir + "config.ron";
-        let etc_path = "/etc/wzmach/config.ron".to_owned();
-        if std::path::Path::new(&local_path).exists() {
-            local_path
-        } else {
-            etc_path
+fn load_config(mb_path: Option<String>) -> Result<config::Config, error::Error> {
+    match mb_path {
+        Some(path) => config::Config::load(path),
+        None => {
+            let home = env_var("HOME")?;
+            let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+                Some(_) => env_var("XDG_CONFIG_HOME")?,
+                None => home + "/.config",
+            };
+            let config_dir = config_home + "/wzmach/";
+
+            // System config provides defaults, user config extends it
+            let local_path = config_dir + "config.ron";
+            let etc_path = "/etc/wzmach/config.ron".to_owned();
+            config::Config::load_layered(&[etc_path, local_path])
         }
-    });
-    config::Config::load(config_path)
+    }
+}
+
+/// Edge swipes need to know where the fingers started, and only touchscreens
+/// tell that; touchpads never start an edge swipe. The config may be shared
+/// with machines that have one, or one may be plugged in later
+fn check_edge_swipes(
+    triggers: &[gesture_event::trigger::Binding],
+    producer: &input_producer::GestureProducer,
+) {
+    use gesture_event::trigger::Trigger;
+    if producer.has_touchscreen() {
+        return;
+    }
+    for b in triggers
+        .iter()
+        .filter(|b| matches!(b.trigger, Trigger::EdgeSwipe(_)))
+    {
+        log::warn!(
+            "Trigger {} is an EdgeSwipe, which only works on touchscreens, and none is connected",
+            b.name
+        );
+    }
+}
+
+fn env_var(name: &'static str) -> Result<String, error::Error> {
+    std::env::var_os(name)
+        .ok_or(error::Error::MissingEnv(name))?
+        .into_string()
+        .map_err(|_| error::Error::InvalidEnv(name))
 }
 
-fn run(config: config::Config) {
+fn run(config: config::Config) -> Result<(), error::Error> {
     use crate::{action::consumer::EventConsumerExt, gesture_event::EventAdapterExt};
 
     // read config
     let is_wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
-    let (triggers, mut actions) = config.make_triggers(is_wayland);
-    log::info!("Starting up");
+    let mouse_button = config.mouse_gesture_button();
+    let config::Bindings {
+        triggers,
+        mut actions,
+        layers,
+        gestures,
+    } = config.make_triggers(is_wayland)?;
+    let mut producer = input_producer::GestureProducer::new()?;
+    if let Some(button) = mouse_button {
+        producer = producer.with_mouse_gestures(button)?;
+    }
+    check_edge_swipes(&triggers, &producer);
+    log::info!("Starting up with {} devices", producer.devices().len());
 
     // run
-    input_producer::GestureProducer::new()
+    let device_name = producer.device_name();
+    let wakeup = producer.wakeup();
+    producer
+        .by_ref()
         .adapt_events(&triggers)
+        .with_layers(layers)
+        .with_device_name(device_name)
+        .with_wakeup(wakeup)
+        .with_gesture_count(gestures)
         .consume_events(&mut actions);
+    producer.take_error().map_or(Ok(()), Err)
+}
+
+fn startup_error(e: error::Error) {
+    // input can break after we have successfully started
+    let summary = match e {
+        error::Error::Poll(_) | error::Error::Dispatch(_) => "Wzmach stopped",
+        _ => "Wzmach failed to start",
+    };
+    log::error!("{}: {}", summary, e);
+    let body = match e.hint() {
+        Some(hint) => {
+            log::info!("Hint: {}", hint);
+            format!("{}\n{}", e, hint)
+        }
+        None => format!("{}", e),
+    };
+    let shown = notify_rust::Notification::new()
+        .summary(summary)
+        .body(&body)
+        .show();
+    if let Err(ne) = shown {
+        log::error!("Could not show notification: {}", ne);
+    }
+    std::process::exit(1);
 }
 
-fn startup_error(e: std::io::Error) {
-    log::error!("Failed to start up: {}", e);
-    notify_rust::Notification::new()
-        .summary("Wzmach failed to start")
-        .body(&format!("{}", e))
-        .show()
-        .unwrap();
+fn debug_error(e: error::Error) {
+    log::error!("{}", e);
+    if let Some(hint) = e.hint() {
+        log::info!("Hint: {}", hint);
+    }
+    std::process::exit(1);
 }
 
-fn debug_events() {
+fn debug_events() -> Result<(), error::Error> {
     use crate::gesture_event::EventAdapterExt;
 
-    let producer = input_producer::GestureProducer::new();
+    let mut producer = input_producer::GestureProducer::new()?;
     log::debug!("Created input connection");
     let triggers = {
         let mut ts = Vec::new();
         use common::{Direction, PinchDirection, RotateDirection};
         use gesture_event::trigger::*;
-        for fingers in 2..5 {
+        for fingers in 1..5 {
             for repeated in [false, true] {
                 ts.push(Trigger::Swipe(CardinalTrigger {
                     fingers,
@@ -153,6 +268,23 @@ fn debug_events() {
                     distance: 100.0,
                     repeated,
                 }));
+                for (edge, direction) in [
+                    (Direction::Left, Direction::Right),
+                    (Direction::Right, Direction::Left),
+                    (Direction::Up, Direction::Down),
+                    (Direction::Down, Direction::Up),
+                ] {
+                    ts.push(Trigger::EdgeSwipe(EdgeTrigger {
+                        swipe: CardinalTrigger {
+                            fingers,
+                            direction,
+                            distance: 100.0,
+                            repeated,
+                        },
+                        edge,
+                        margin: 0.05,
+                    }));
+                }
                 ts.push(Trigger::Pinch(PinchTrigger {
                     fingers,
                     direction: PinchDirection::In,
@@ -203,15 +335,102 @@ fn debug_events() {
                 }));
             }
             ts.push(Trigger::Hold(HoldTrigger { fingers, time: 50 }));
+            for count in 1..4 {
+                ts.push(Trigger::Tap(TapTrigger {
+                    fingers,
+                    count,
+                    time: 200,
+                    interval: 300,
+                }));
+            }
         }
         ts
     };
-    let events = producer.adapt_events(&triggers);
+    let triggers = triggers
+        .into_iter()
+        .enumerate()
+        .map(|(i, t)| gesture_event::trigger::Binding::new(format!("#{}", i), t))
+        .collect::<Vec<_>>();
+    let wakeup = producer.wakeup();
+    let events = producer
+        .by_ref()
+        .adapt_events(&triggers)
+        .with_wakeup(wakeup);
+    for event in events {
+        for fired in event {
+            log::debug!("triggered: {:?}", triggers[fired.index].trigger);
+        }
+    }
+    producer.take_error().map_or(Ok(()), Err)
+}
+
+fn debug_config_gestures(config: config::Config) -> Result<(), error::Error> {
+    use crate::gesture_event::EventAdapterExt;
+
+    let is_wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+    let config::Bindings {
+        triggers,
+        mut actions,
+        layers,
+        gestures,
+    } = config.make_triggers(is_wayland)?;
+    let mut producer = input_producer::GestureProducer::new()?;
+    check_edge_swipes(&triggers, &producer);
+    let device_name = producer.device_name();
+    let wakeup = producer.wakeup();
+    let events = producer
+        .by_ref()
+        .adapt_events(&triggers)
+        .with_layers(layers.clone())
+        .with_device_name(device_name)
+        .with_wakeup(wakeup)
+        .with_gesture_count(gestures);
     for event in events {
-        for i in event {
-            log::debug!("triggered: {:?}", triggers[i]);
+        for fired in event {
+            let i = fired.index;
+            log::debug!(
+                "triggered {} in layer {:?}: {:?}",
+                fired.name,
+                triggers[i].layer,
+                triggers[i].trigger
+            );
+            if actions[i].internal() {
+                if let Err(e) = actions[i].execute(&fired.context) {
+                    log::error!("Trigger {}: {}", fired.name, e);
+                }
+            }
         }
+        log::debug!("Active layer: {:?}", layers.active());
+    }
+    producer.take_error().map_or(Ok(()), Err)
+}
+
+fn train_shape(name: &str) -> Result<(), error::Error> {
+    use input_producer::event::{Gesture, InputEvent};
+
+    let mut producer = input_producer::GestureProducer::new()?;
+    println!("Draw {} with a swipe", name);
+    for event in producer.by_ref() {
+        let path = match event {
+            InputEvent::Ended(Gesture::Swipe(swipe), _, _) => swipe.path,
+            _ => continue,
+        };
+        let points = match gesture_event::shape::normalize(&path) {
+            Some(points) => points,
+            None => {
+                println!("That was too short, try again");
+                continue;
+            }
+        };
+        let points = points
+            .iter()
+            .map(|(x, y)| format!("({:.1}, {:.1})", x, y))
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("(\n    name: {:?},\n    points: [{}],\n),", name, points);
+        return Ok(());
     }
+    producer.take_error().map_or(Ok(()), Err)
 }
 
 #[cfg(test)]